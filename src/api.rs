@@ -1,16 +1,114 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::event::{Print, SilentOutput};
+use crate::{
+    event::{Print, SilentOutput},
+    rapid::types::Repo,
+};
 
+pub const DEFAULT_REGISTRY_URL: &str = "https://repos.springrts.com/repos.gz";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MetadataSource {
     Local,
     FileApi,
     RestApi(String),
+    /// Tried in order until one answers without a network error or a missing local file
+    Chain(Vec<MetadataSource>),
+}
+
+impl MetadataSource {
+    /// The sources to try in order, with nested chains flattened.
+    pub fn sources(&self) -> Vec<&MetadataSource> {
+        match self {
+            MetadataSource::Chain(sources) => sources.iter().flat_map(|s| s.sources()).collect(),
+            source => vec![source],
+        }
+    }
+}
+
+/// Parses `local`, `file` and `rest=<url>`, or a comma separated chain of them.
+impl FromStr for MetadataSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(',') {
+            return s
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map(MetadataSource::Chain);
+        }
+        match s.trim() {
+            "local" => Ok(MetadataSource::Local),
+            "file" => Ok(MetadataSource::FileApi),
+            source => match source.strip_prefix("rest=") {
+                Some(url) if !url.is_empty() => Ok(MetadataSource::RestApi(url.to_owned())),
+                _ => Err(format!(
+                    "invalid metadata source {source:?}, expected local, file or rest=<url>"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MetadataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataSource::Local => write!(f, "local"),
+            MetadataSource::FileApi => write!(f, "file"),
+            MetadataSource::RestApi(url) => write!(f, "rest={url}"),
+            MetadataSource::Chain(sources) => {
+                let sources: Vec<String> = sources.iter().map(|s| s.to_string()).collect();
+                write!(f, "{}", sources.join(","))
+            }
+        }
+    }
+}
+
+/// How HTTP requests are retried after connection failures, timeouts and server errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of tries, 1 means no retries
+    pub attempts: u32,
+    /// Wait between tries
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            delay: Duration::from_secs(1),
+        }
+    }
 }
 
 pub struct DownloadOptions {
     pub metadata_source: MetadataSource,
     pub print: Arc<Box<dyn Print>>,
+    /// How long to wait for other processes to release the root
+    pub lock_timeout: Duration,
+    /// Number of files verified in parallel
+    pub verify_concurrency: usize,
+    /// Skip verifying pool files that haven't changed since they were last verified
+    pub use_verification_cache: bool,
+    /// Move corrupt files to the quarantine folder instead of overwriting them when repairing
+    pub quarantine: bool,
+    /// Never access the network, requests fail with a network error instead
+    pub offline: bool,
+    pub registry_url: String,
+    /// Repos that aren't in the registry, looked up before it
+    pub extra_repos: Vec<Repo>,
+    pub retry: RetryPolicy,
+    /// Maximum time to wait for the server to respond to an HTTP request
+    pub timeout: Option<Duration>,
+    /// Root shared between roots: valid pool files found there are linked instead of downloaded,
+    /// and downloaded files are linked into it
+    pub cache_root: Option<PathBuf>,
 }
 
 impl Default for DownloadOptions {
@@ -18,6 +116,18 @@ impl Default for DownloadOptions {
         DownloadOptions {
             metadata_source: MetadataSource::FileApi,
             print: Arc::new(Box::new(SilentOutput {})),
+            lock_timeout: Duration::from_secs(60),
+            verify_concurrency: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            use_verification_cache: false,
+            quarantine: false,
+            offline: false,
+            registry_url: DEFAULT_REGISTRY_URL.to_owned(),
+            extra_repos: Vec::new(),
+            retry: RetryPolicy::default(),
+            timeout: None,
+            cache_root: None,
         }
     }
 }
@@ -30,3 +140,30 @@ impl DownloadOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_metadata_source() {
+        for s in [
+            "local",
+            "file",
+            "rest=http://localhost:8080",
+            "rest=http://a,file,local",
+        ] {
+            let source: MetadataSource = s.parse().unwrap();
+            assert_eq!(source.to_string(), s);
+        }
+        assert_eq!(
+            "rest=http://a,local".parse::<MetadataSource>().unwrap(),
+            MetadataSource::Chain(vec![
+                MetadataSource::RestApi("http://a".to_owned()),
+                MetadataSource::Local
+            ])
+        );
+        assert!("rest=".parse::<MetadataSource>().is_err());
+        assert!("ftp".parse::<MetadataSource>().is_err());
+    }
+}
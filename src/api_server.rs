@@ -0,0 +1,172 @@
+//! REST metadata server answering the queries of [`MetadataSource::RestApi`](crate::api::MetadataSource)
+//! from a local rapid root:
+//!
+//! - `GET /repo/{name}`: the repo from the registry
+//! - `GET /sdp/{fullname}`: the sdp (by rapid name, or archive name if there's no `:`) and its repo
+//! - `GET /files/{md5}`: the files of a downloaded sdp
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    metadata::metadata_rest::{FileResponse, RapidResponse, RepoResponse, SdpResponse},
+    rapid::{parsing, rapid_store::RapidStore, types::Repo},
+};
+
+/// Binds `addr` and returns the bound address (useful with port 0) and the future running the server.
+pub fn bind(
+    rapid_store: RapidStore,
+    addr: &SocketAddr,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), Error>>), Error> {
+    let rapid_store = Arc::new(rapid_store);
+    let make_service = make_service_fn(move |_| {
+        let rapid_store = rapid_store.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let rapid_store = rapid_store.clone();
+                async move { Ok::<_, Infallible>(handle(&rapid_store, req)) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(addr)
+        .map_err(|e| Error::network(addr, e))?
+        .serve(make_service);
+    let local_addr = server.local_addr();
+    let url = format!("http://{local_addr}");
+
+    Ok((local_addr, async move {
+        server.await.map_err(|e| Error::network(url, e))
+    }))
+}
+
+fn handle(rapid_store: &RapidStore, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let Some((route, arg)) = req.uri().path().trim_start_matches('/').split_once('/') else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    let Ok(arg) = percent_decode_str(arg).decode_utf8() else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+
+    let result = match route {
+        "repo" => find_repo(rapid_store, &arg)
+            .map(|repo| repo.map(|(id, repo)| json(&repo_response(id, repo)))),
+        "sdp" => find_sdp(rapid_store, &arg).map(|sdp| sdp.map(|sdp| json(&sdp))),
+        "files" => find_files(rapid_store, &arg).map(|files| files.map(|files| json(&files))),
+        _ => Ok(None),
+    };
+    match result {
+        Ok(Some(response)) => response,
+        Ok(None) => status(StatusCode::NOT_FOUND),
+        Err(err) => {
+            let mut response = Response::new(Body::from(err.to_string()));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(_) => status(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn repo_response(id: i32, repo: Repo) -> RepoResponse {
+    RepoResponse {
+        id,
+        name: repo.name,
+        url: repo.url,
+    }
+}
+
+/// Ids are positions in the registry and versions files, starting at 1.
+fn find_repo(rapid_store: &RapidStore, name: &str) -> Result<Option<(i32, Repo)>, Error> {
+    let repos = parsing::parse_repos_from_file(&rapid_store.find_registry_path())?;
+    Ok(repos
+        .into_iter()
+        .enumerate()
+        .find(|(_, repo)| repo.name == name)
+        .map(|(i, repo)| (i as i32 + 1, repo)))
+}
+
+fn find_sdp(rapid_store: &RapidStore, fullname: &str) -> Result<Option<SdpResponse>, Error> {
+    let repos = parsing::parse_repos_from_file(&rapid_store.find_registry_path())?;
+    let repo_name = fullname.split_once(':').map(|(repo, _)| repo);
+
+    for (repo_index, repo) in repos.into_iter().enumerate() {
+        if repo_name.is_some_and(|name| name != repo.name) {
+            continue;
+        }
+        let repo_path = rapid_store.find_repo_path(&repo);
+        if !repo_path.exists() {
+            continue;
+        }
+        let found = parsing::read_rapid_from_file(&repo_path)?
+            .into_iter()
+            .enumerate()
+            .find(|(_, sdp)| match repo_name {
+                Some(_) => sdp.rapid_name == fullname,
+                None => sdp.archive_name == fullname,
+            });
+        if let Some((sdp_index, sdp)) = found {
+            let repo_id = repo_index as i32 + 1;
+            return Ok(Some(SdpResponse {
+                rapid: RapidResponse {
+                    id: sdp_index as i32 + 1,
+                    repo_id,
+                    fullname: sdp.rapid_name,
+                    hash: sdp.md5,
+                    something: sdp.depends,
+                    alias: sdp.archive_name,
+                },
+                repo: repo_response(repo_id, repo),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_files(rapid_store: &RapidStore, md5: &str) -> Result<Option<Vec<FileResponse>>, Error> {
+    if md5.len() != 32 || !md5.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let sdp_path = rapid_store.find_sdp_path_from_md5(md5);
+    if !sdp_path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        parsing::load_sdp_packages_from_file(&sdp_path)?
+            .into_iter()
+            .map(|package| FileResponse {
+                md5: String::from_utf8_lossy(&package.md5).into_owned(),
+                crc32: u32::from_le_bytes(package.crc32),
+                size: package.size,
+                name: package.name,
+            })
+            .collect(),
+    ))
+}
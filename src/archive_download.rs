@@ -0,0 +1,274 @@
+//! Plain HTTP downloads of packed archives (`.sd7`, `.sdz`), which is how maps and some games are
+//! distributed outside of rapid.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+
+use crate::{
+    api::DownloadOptions, error::Error, file_download, gc::TEMP_EXTENSION,
+    rapid::rapid_store::RapidStore, validation::FileError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveType {
+    Map,
+    Game,
+}
+
+impl ArchiveType {
+    /// Folder of the root the archives are stored in.
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            ArchiveType::Map => "maps",
+            ArchiveType::Game => "games",
+        }
+    }
+}
+
+impl FromStr for ArchiveType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "map" => Ok(ArchiveType::Map),
+            "game" => Ok(ArchiveType::Game),
+            _ => Err(format!("invalid archive type {s:?}, expected map or game")),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveType::Map => write!(f, "map"),
+            ArchiveType::Game => write!(f, "game"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ArchiveReport {
+    pub path: PathBuf,
+    pub md5: String,
+    /// The same file was already there, nothing was written
+    pub already_present: bool,
+}
+
+/// Downloads the archive at `url` into the folder of `archive_type`, named after the last segment
+/// of the URL, and checks it against `md5` if given.
+///
+/// The file is written to a unique temp file first and only renamed into place once complete and
+/// valid.
+/// An existing file with different contents is never overwritten.
+pub async fn download_archive(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    url: &str,
+    archive_type: ArchiveType,
+    md5: Option<&str>,
+) -> Result<ArchiveReport, Error> {
+    let uri = url
+        .parse::<hyper::Uri>()
+        .map_err(|e| Error::network(url, e))?;
+    let file_name = file_name(&uri).ok_or_else(|| {
+        Error::network(url, anyhow::anyhow!("the URL doesn't end with a file name"))
+    })?;
+    let dest = rapid_store
+        .root
+        .join(archive_type.dir_name())
+        .join(&file_name);
+
+    if let Some(md5) = md5 {
+        if dest.exists() && file_md5(&dest)?.eq_ignore_ascii_case(md5) {
+            return Ok(ArchiveReport {
+                path: dest,
+                md5: md5.to_lowercase(),
+                already_present: true,
+            });
+        }
+    }
+
+    // Unique, as several callers may download the same archive; removed when dropped
+    let dir = dest.parent().unwrap_or(&rapid_store.root);
+    fs::create_dir_all(dir).map_err(|e| Error::filesystem(dir, e))?;
+    let temp_file = tempfile::Builder::new()
+        .prefix(&format!(".{file_name}."))
+        .suffix(&format!(".{TEMP_EXTENSION}"))
+        .tempfile_in(dir)
+        .map_err(|e| Error::filesystem(dir, e))?;
+    let (downloaded_md5, mut already_present) =
+        download_to_temp(opts, uri, temp_file.path(), &dest, &file_name, md5).await?;
+    if !already_present {
+        if let Err(err) = temp_file.persist_noclobber(&dest) {
+            // Someone else finished the same download in the meantime
+            if err.error.kind() != io::ErrorKind::AlreadyExists
+                || file_md5(&dest)? != downloaded_md5
+            {
+                return Err(Error::filesystem(&dest, err.error));
+            }
+            already_present = true;
+        }
+    }
+
+    Ok(ArchiveReport {
+        path: dest,
+        md5: downloaded_md5,
+        already_present,
+    })
+}
+
+/// Returns the md5 of the downloaded file and whether `dest` already has the same contents.
+async fn download_to_temp(
+    opts: &DownloadOptions,
+    uri: hyper::Uri,
+    temp_path: &Path,
+    dest: &Path,
+    file_name: &str,
+    md5: Option<&str>,
+) -> Result<(String, bool), Error> {
+    file_download::download_file_allow_http(opts, uri, temp_path, file_name).await?;
+
+    let downloaded_md5 = file_md5(temp_path)?;
+    if md5.is_some_and(|md5| !md5.eq_ignore_ascii_case(&downloaded_md5)) {
+        return Err(Error::Integrity {
+            files: vec![(dest.to_owned(), FileError::WrongHash)],
+        });
+    }
+    if dest.exists() {
+        if file_md5(dest)? != downloaded_md5 {
+            return Err(Error::filesystem(
+                dest,
+                io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "a different file with the same name exists",
+                ),
+            ));
+        }
+        return Ok((downloaded_md5, true));
+    }
+
+    Ok((downloaded_md5, false))
+}
+
+/// The decoded last path segment, if it's usable as a file name.
+fn file_name(uri: &hyper::Uri) -> Option<String> {
+    let segment = uri.path().rsplit('/').next()?;
+    let name = percent_decode_str(segment).decode_utf8().ok()?;
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return None;
+    }
+    Some(name.into_owned())
+}
+
+fn file_md5(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path).map_err(|e| Error::filesystem(path, e))?;
+    let mut hasher = Md5::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| Error::filesystem(path, e))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &[u8] = b"map contents";
+    const WRONG_MD5: &str = "0a1a4a2c1f7d5ae7f4a1f3e1c5a0a4c6";
+
+    fn md5_of(contents: &[u8]) -> String {
+        format!("{:x}", Md5::digest(contents))
+    }
+
+    #[tokio::test]
+    async fn download_into_the_type_folder() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::default();
+        let server = test_utils::serve_files(vec![
+            ("/files/My%20Map.sd7".to_owned(), MAP.to_vec()),
+            ("/files/other/My%20Map.sd7".to_owned(), b"other".to_vec()),
+        ]);
+        let url = format!("{server}/files/My%20Map.sd7");
+
+        let report = download_archive(&rapid_store, &opts, &url, ArchiveType::Map, None)
+            .await
+            .unwrap();
+        assert_eq!(report.path, root.path().join("maps/My Map.sd7"));
+        assert_eq!(report.md5, md5_of(MAP));
+        assert!(!report.already_present);
+        assert_eq!(fs::read(&report.path).unwrap(), MAP);
+
+        let md5 = md5_of(MAP);
+        let report = download_archive(&rapid_store, &opts, &url, ArchiveType::Map, Some(&md5))
+            .await
+            .unwrap();
+        assert!(report.already_present);
+
+        // Same name, different contents
+        let err = download_archive(
+            &rapid_store,
+            &opts,
+            &format!("{server}/files/other/My%20Map.sd7"),
+            ArchiveType::Map,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Filesystem { .. }), "{err:?}");
+        assert_eq!(fs::read(&report.path).unwrap(), MAP);
+        assert_eq!(fs::read_dir(root.path().join("maps")).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_of_the_same_archive() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::default();
+        let server = test_utils::serve_files(vec![("/map.sd7".to_owned(), MAP.to_vec())]);
+        let url = format!("{server}/map.sd7");
+
+        let (first, second) = tokio::join!(
+            download_archive(&rapid_store, &opts, &url, ArchiveType::Map, None),
+            download_archive(&rapid_store, &opts, &url, ArchiveType::Map, None)
+        );
+        assert_eq!(first.unwrap().path, second.unwrap().path);
+        assert_eq!(fs::read(root.path().join("maps/map.sd7")).unwrap(), MAP);
+        // No temp files left behind
+        assert_eq!(fs::read_dir(root.path().join("maps")).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn wrong_checksum_writes_nothing() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let server = test_utils::serve_files(vec![("/game.sdz".to_owned(), MAP.to_vec())]);
+
+        let err = download_archive(
+            &rapid_store,
+            &DownloadOptions::default(),
+            &format!("{server}/game.sdz"),
+            ArchiveType::Game,
+            Some(WRONG_MD5),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Integrity { .. }), "{err:?}");
+        assert_eq!(fs::read_dir(root.path().join("games")).unwrap().count(), 0);
+
+        let err = download_archive(
+            &rapid_store,
+            &DownloadOptions::default(),
+            &format!("{server}/"),
+            ArchiveType::Game,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+    }
+}
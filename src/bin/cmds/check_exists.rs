@@ -1,11 +1,39 @@
-use sprd::{rapid, validation};
-
-pub fn check_exists(rapid_store: &rapid::rapid_store::RapidStore, sdp_md5: &str) {
-    if validation::check_if_sdp_needs_download(rapid_store, sdp_md5) {
-        println!("Download necessary");
-        std::process::exit(1);
-    } else {
-        println!("No download necessary");
-        std::process::exit(0);
+use sprd::{api::DownloadOptions, error::Error, event::Event, metadata, rapid, validation};
+
+use super::{CmdError, CmdResult};
+
+pub async fn check_exists(
+    rapid_store: &rapid::rapid_store::RapidStore,
+    opts: &DownloadOptions,
+    name: &str,
+) -> CmdResult {
+    let sdp_md5 = metadata::resolve_sdp_md5(rapid_store, opts, name)
+        .await?
+        .ok_or_else(|| {
+            CmdError::Incomplete(format!("Download necessary: {name} isn't downloaded"))
+        })?;
+
+    match validation::completion_by_sdp_md5(rapid_store, &sdp_md5) {
+        Ok(completion) => opts.print.event(Event::Info(format!(
+            "{} of {} files present ({:.1}%)",
+            completion.present_files,
+            completion.total_files(),
+            completion.percentage()
+        ))),
+        Err(Error::NotFound(_)) => {
+            opts.print
+                .event(Event::Info("Sdp file is missing".to_owned()));
+        }
+        Err(_) => opts
+            .print
+            .event(Event::Info("Sdp file is corrupt".to_owned())),
     }
+
+    if validation::check_if_sdp_needs_download(rapid_store, opts, &sdp_md5).await {
+        return Err(CmdError::Incomplete("Download necessary".to_owned()));
+    }
+
+    opts.print
+        .event(Event::Info("No download necessary".to_owned()));
+    Ok(())
 }
@@ -0,0 +1,11 @@
+use sprd::{api::DownloadOptions, config::Config, event::Event};
+
+use super::CmdResult;
+
+pub fn config_show(config: &Config, opts: &DownloadOptions) -> CmdResult {
+    for (key, value, origin) in config.entries() {
+        opts.print
+            .event(Event::Info(format!("{key} = {value}  # {origin}")));
+    }
+    Ok(())
+}
@@ -1,7 +1,48 @@
-use sprd::{api::DownloadOptions, rapid::rapid_store::RapidStore, rapid_download};
+use sprd::{api::DownloadOptions, event::Event, rapid::rapid_store::RapidStore, rapid_download};
 
-pub async fn download(rapid_store: &RapidStore, opts: &DownloadOptions, fullname: &str) {
-    if let Err(err) = rapid_download::download(rapid_store, opts, fullname).await {
-        println!("Failed to download {fullname}. Error: {err:?}");
+use super::{CmdError, CmdResult};
+
+pub async fn download(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> CmdResult {
+    let report = rapid_download::download(rapid_store, opts, fullname).await?;
+
+    if report.files_linked > 0 {
+        opts.print.event(Event::Info(format!(
+            "Took {} files from the cache root",
+            report.files_linked
+        )));
+    }
+    if report.files_requested == 0 {
+        opts.print.event(Event::Info(format!(
+            "{} is already downloaded",
+            report.sdp.archive_name
+        )));
+        return Ok(());
+    }
+    opts.print.event(Event::Info(format!(
+        "Downloaded {} of {} files ({} bytes) for {} in {:.1}s",
+        report.files_written,
+        report.files_requested,
+        report.bytes_transferred,
+        report.sdp.archive_name,
+        report.duration.as_secs_f64()
+    )));
+    if report.trailing_bytes != 0 {
+        return Err(CmdError::Corrupt(format!(
+            "The streamer sent {} unexpected bytes after the last file",
+            report.trailing_bytes
+        )));
+    }
+    if !report.is_success() {
+        return Err(CmdError::Corrupt(format!(
+            "{} files failed to download correctly",
+            report
+                .files_failed
+                .max(report.files_requested - report.files_written)
+        )));
     }
+    Ok(())
 }
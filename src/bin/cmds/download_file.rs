@@ -0,0 +1,48 @@
+use sprd::{
+    api::DownloadOptions,
+    archive_download::{self, ArchiveReport, ArchiveType},
+    content_resolver::{self, ContentResolver},
+    event::Event,
+    rapid::rapid_store::RapidStore,
+};
+
+use super::CmdResult;
+
+pub async fn download_file(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    url: &str,
+    archive_type: ArchiveType,
+    md5: Option<&str>,
+) -> CmdResult {
+    let report =
+        archive_download::download_archive(rapid_store, opts, url, archive_type, md5).await?;
+    print_report(opts, &report);
+    Ok(())
+}
+
+pub async fn download_map(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    resolver: &impl ContentResolver,
+    name: &str,
+) -> CmdResult {
+    let report =
+        content_resolver::download_by_name(rapid_store, opts, resolver, name, ArchiveType::Map)
+            .await?;
+    print_report(opts, &report);
+    Ok(())
+}
+
+fn print_report(opts: &DownloadOptions, report: &ArchiveReport) {
+    let message = if report.already_present {
+        "is already downloaded"
+    } else {
+        "downloaded"
+    };
+    opts.print.event(Event::Info(format!(
+        "{} {message} (md5 {})",
+        report.path.display(),
+        report.md5
+    )));
+}
@@ -0,0 +1,9 @@
+use sprd::{api::DownloadOptions, disk_usage, event::Event, rapid::rapid_store::RapidStore};
+
+use super::CmdResult;
+
+pub fn du(rapid_store: &RapidStore, opts: &DownloadOptions) -> CmdResult {
+    let usage = disk_usage::disk_usage(rapid_store)?;
+    opts.print.event(Event::DiskUsage(usage));
+    Ok(())
+}
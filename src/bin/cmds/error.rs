@@ -0,0 +1,102 @@
+use std::process::ExitCode;
+
+use thiserror::Error;
+
+use sprd::error::Error;
+
+/// Why a command failed. Each kind has its own process exit code:
+///
+/// | code | meaning                                   |
+/// |------|-------------------------------------------|
+/// | 0    | success                                   |
+/// | 1    | other failure                             |
+/// | 2    | invalid arguments                         |
+/// | 3    | item not found                            |
+/// | 4    | network failure                           |
+/// | 5    | corrupt or invalid files                  |
+/// | 6    | not fully downloaded (`check-exists`)     |
+/// | 7    | root folder locked by another process     |
+/// | 8    | filesystem error                          |
+#[derive(Debug, Error)]
+pub enum CmdError {
+    #[error("{0}")]
+    Failure(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Network(String),
+
+    #[error("{0}")]
+    Corrupt(String),
+
+    #[error("{0}")]
+    Incomplete(String),
+
+    #[error("{0}")]
+    LockBusy(String),
+
+    #[error("{0}")]
+    Filesystem(String),
+}
+
+pub type CmdResult = Result<(), CmdError>;
+
+impl CmdError {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.code())
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            CmdError::Failure(_) => 1,
+            CmdError::NotFound(_) => 3,
+            CmdError::Network(_) => 4,
+            CmdError::Corrupt(_) => 5,
+            CmdError::Incomplete(_) => 6,
+            CmdError::LockBusy(_) => 7,
+            CmdError::Filesystem(_) => 8,
+        }
+    }
+}
+
+/// Error message including its sources, e.g. "download failed: invalid server response: ...".
+fn describe(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(&format!(": {err}"));
+        source = err.source();
+    }
+    message
+}
+
+impl From<Error> for CmdError {
+    fn from(err: Error) -> Self {
+        let message = describe(&err);
+        match err {
+            Error::NotFound(_) => CmdError::NotFound(message),
+            Error::Network { .. } => CmdError::Network(message),
+            Error::Metadata { .. } | Error::Integrity { .. } => CmdError::Corrupt(message),
+            Error::Locked(_) => CmdError::LockBusy(message),
+            Error::Config { .. } => CmdError::Failure(message),
+            Error::Filesystem { .. } => CmdError::Filesystem(message),
+        }
+    }
+}
+
+impl From<anyhow::Error> for CmdError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Error>() {
+            Ok(err) => err.into(),
+            Err(err) => CmdError::Failure(format!("{err:#}")),
+        }
+    }
+}
+
+impl From<std::io::Error> for CmdError {
+    fn from(err: std::io::Error) -> Self {
+        CmdError::Filesystem(err.to_string())
+    }
+}
@@ -1,78 +1,89 @@
-use tokio::fs;
-
 use sprd::{
     api::DownloadOptions,
+    error::Error,
     event::Event,
     rapid,
-    validation::{self, FileError, ValidityErrors},
+    repair::{self, RepairReport},
+    validation,
 };
 
-use super::download;
+use super::{verify::display_name, CmdError, CmdResult};
 
 pub async fn fix(
     rapid_store: &rapid::rapid_store::RapidStore,
     opts: &DownloadOptions,
     fullname: &str,
-) {
-    for attempt in 0..5 {
+) -> CmdResult {
+    let report = repair::repair(rapid_store, opts, fullname).await?;
+    print_report(opts, &report)?;
+
+    opts.print
+        .event(Event::Info(format!("Successfully verified: {fullname}")));
+    Ok(())
+}
+
+fn print_report(opts: &DownloadOptions, report: &RepairReport) -> CmdResult {
+    if report.sdp_redownloaded {
         opts.print
-            .event(Event::Info(format!("Fix attempt {attempt}.")));
-        let success = fix_attempt(rapid_store, opts, fullname).await;
-        if success {
-            opts.print.event(Event::Info("Success".to_owned()));
-            return;
-        }
+            .event(Event::Info("Downloaded the sdp file again.".to_owned()));
+    }
+    for path in report.quarantined_files.iter() {
+        opts.print
+            .event(Event::Info(format!("Quarantined {path:?}")));
+    }
+    for path in report.fixed_files.iter() {
+        opts.print.event(Event::Info(format!("Fixed {path:?}")));
+    }
+    for (path, file_error) in report.failed_files.iter() {
+        opts.print
+            .event(Event::Error(format!("{path:?} is still {file_error:?}")));
     }
 
-    opts.print.event(Event::Error(
-        "Failed to fix after five attempts.".to_owned(),
-    ));
+    if !report.is_success() {
+        return Err(CmdError::Corrupt(format!(
+            "Failed to fix {} files",
+            report.failed_files.len()
+        )));
+    }
+    Ok(())
 }
 
-async fn fix_attempt(
+pub async fn fix_all(
     rapid_store: &rapid::rapid_store::RapidStore,
     opts: &DownloadOptions,
-    fullname: &str,
-) -> bool {
-    let results = validation::validate_by_fullname(rapid_store, opts, fullname).await;
+) -> CmdResult {
+    let results = validation::validate_all(rapid_store, opts).await?;
 
-    match results {
-        Ok(()) => {
+    let invalid: Vec<_> = results
+        .into_iter()
+        .filter(|validity| validity.result.is_err())
+        .collect();
+    let mut failed = 0;
+    for validity in invalid.iter() {
+        let name = display_name(rapid_store, &validity.sdp_md5).await;
+        opts.print.event(Event::Info(format!("Fixing {name}")));
+        // Repaired by md5, as a tag may point to another version by now
+        let invalid_files = match &validity.result {
+            Err(Error::Integrity { files }) => Some(files.clone()),
+            _ => None,
+        };
+        let result = repair::repair_by_sdp_md5(rapid_store, opts, &validity.sdp_md5, invalid_files)
+            .await
+            .map_err(CmdError::from)
+            .and_then(|report| print_report(opts, &report));
+        if let Err(err) = result {
+            failed += 1;
             opts.print
-                .event(Event::Info(format!("Successfully verified: {fullname}")));
-            // println!("Successfully verified {fullname}");
-            return true;
-        }
-        Err(ValidityErrors::MissingSdp) => {
-            opts.print
-                .event(Event::Error("Sdp file is missing.".to_owned()));
-        }
-        Err(ValidityErrors::MetadataQueryError(error)) => {
-            opts.print
-                .event(Event::Error(format!("Metadata query error: {error:?}")));
-        }
-        Err(ValidityErrors::InvalidFiles { files }) => {
-            let mut removed_files = 0;
-            for (path, file_error) in files {
-                match file_error {
-                    FileError::Corrupt | FileError::WrongHash => {
-                        if let Err(e) = fs::remove_file(&path).await {
-                            opts.print
-                                .event(Event::Error(format!("Error: {e:?} for {path:?}")));
-                        } else {
-                            removed_files += 1;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            opts.print.event(Event::Info(format!(
-                "Removed {removed_files} corrupt files"
-            )));
+                .event(Event::Error(format!("Cannot fix {name}: {err}")));
         }
     }
 
-    download(rapid_store, opts, fullname).await;
-
-    false
+    opts.print.event(Event::Info(format!(
+        "Found {} sdps needing a fix, failed to fix {failed}",
+        invalid.len()
+    )));
+    if failed > 0 {
+        return Err(CmdError::Corrupt(format!("Failed to fix {failed} sdps")));
+    }
+    Ok(())
 }
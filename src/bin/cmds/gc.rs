@@ -0,0 +1,26 @@
+use indicatif::HumanBytes;
+
+use sprd::{api::DownloadOptions, event::Event, gc, rapid::rapid_store::RapidStore};
+
+use super::CmdResult;
+
+pub fn gc(rapid_store: &RapidStore, opts: &DownloadOptions, dry_run: bool) -> CmdResult {
+    let report = gc::collect_garbage(rapid_store, dry_run)?;
+
+    let action = if dry_run { "Would remove" } else { "Removed" };
+    for path in report.unreferenced_files.iter() {
+        opts.print
+            .event(Event::Info(format!("{action} unreferenced file {path:?}")));
+    }
+    for path in report.temp_files.iter() {
+        opts.print
+            .event(Event::Info(format!("{action} temp file {path:?}")));
+    }
+    opts.print.event(Event::Info(format!(
+        "{action} {} unreferenced and {} temp files, reclaiming {}",
+        report.unreferenced_files.len(),
+        report.temp_files.len(),
+        HumanBytes(report.reclaimed_bytes)
+    )));
+    Ok(())
+}
@@ -7,6 +7,8 @@ use sprd::{
     rapid::{self, rapid_store::RapidStore},
 };
 
+use super::{CmdError, CmdResult};
+
 #[derive(Error, Debug)]
 enum Errors {
     #[error("no such repo")]
@@ -17,22 +19,22 @@ pub async fn meta_download_repo(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo: Option<&str>,
-) {
-    match repo {
-        Some(repo) => handle_errors(download_one_repo(rapid_store, opts, repo).await, opts),
-        None => handle_errors(download_all_repos(rapid_store, opts).await, opts),
+) -> CmdResult {
+    let result = match repo {
+        Some(repo) => download_one_repo(rapid_store, opts, repo).await,
+        None => download_all_repos(rapid_store, opts).await,
     };
+    handle_errors(result)?;
+
+    opts.print.event(Event::Info("Download success".to_owned()));
+    Ok(())
 }
 
-fn handle_errors(result: anyhow::Result<()>, opts: &DownloadOptions) {
-    match result {
-        Ok(()) => opts
-            .print
-            .event(Event::Error("Download success".to_owned())),
-        Err(err) => opts.print.event(Event::Error(format!(
-            "Failed to download repository: {err}"
-        ))),
-    }
+fn handle_errors(result: anyhow::Result<()>) -> CmdResult {
+    result.map_err(|err| match err.downcast_ref::<Errors>() {
+        Some(Errors::NoSuchRepo) => CmdError::NotFound(err.to_string()),
+        None => err.into(),
+    })
 }
 
 async fn download_one_repo(
@@ -57,7 +59,7 @@ async fn download_one_repo(
     let repo = repo_registry
         .into_iter()
         .find(|r| r.name == repo)
-        .ok_or_else(|| Box::new(Errors::NoSuchRepo))?;
+        .ok_or(Errors::NoSuchRepo)?;
 
     file_download::download_repo(rapid_store, opts, &repo)
         .await
@@ -74,10 +76,9 @@ async fn download_all_repos(
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
-    use crate::rapid;
+    use sprd::rapid;
 
     #[tokio::test]
     async fn download_one_repo_with_clean_install() {
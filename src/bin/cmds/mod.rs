@@ -1,15 +1,33 @@
 pub mod check_exists;
+pub mod config;
 pub mod download;
+pub mod download_file;
+pub mod du;
+pub mod error;
 pub mod fix;
+pub mod gc;
 pub mod meta_download_registry;
 pub mod meta_download_repo;
 pub mod meta_download_sdp;
+pub mod prune;
+pub mod quarantine;
+pub mod remove;
+pub mod serve_api;
 pub mod verify;
 
 pub use check_exists::check_exists;
+pub use config::config_show;
 pub use download::download;
-pub use fix::fix;
+pub use download_file::{download_file, download_map};
+pub use du::du;
+pub use error::{CmdError, CmdResult};
+pub use fix::{fix, fix_all};
+pub use gc::gc;
 pub use meta_download_registry::meta_download_registry;
 pub use meta_download_repo::meta_download_repo;
 pub use meta_download_sdp::meta_download_sdp;
-pub use verify::verify;
+pub use prune::prune;
+pub use quarantine::{quarantine_list, quarantine_purge};
+pub use remove::remove;
+pub use serve_api::serve_api;
+pub use verify::{verify, verify_all};
@@ -0,0 +1,34 @@
+use indicatif::HumanBytes;
+
+use sprd::{api::DownloadOptions, event::Event, metadata, prune, rapid::rapid_store::RapidStore};
+
+use super::{CmdError, CmdResult};
+
+pub async fn prune(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    keep: usize,
+    repo: Option<&str>,
+    pins: &[String],
+) -> CmdResult {
+    let mut pinned = Vec::new();
+    for pin in pins {
+        let sdp_md5 = metadata::resolve_sdp_md5(rapid_store, opts, pin)
+            .await?
+            .ok_or_else(|| CmdError::NotFound(format!("No such pinned item: {pin}")))?;
+        pinned.push(sdp_md5);
+    }
+
+    let report = prune::prune(rapid_store, keep, repo, &pinned)?;
+    for sdp_md5 in report.removed_sdps.iter() {
+        opts.print
+            .event(Event::Info(format!("Removed sdp {sdp_md5}")));
+    }
+    opts.print.event(Event::Info(format!(
+        "Kept {} and removed {} sdps, reclaiming {}",
+        report.kept_sdps.len(),
+        report.removed_sdps.len(),
+        HumanBytes(report.gc.reclaimed_bytes)
+    )));
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use sprd::{api::DownloadOptions, event::Event, quarantine, rapid::rapid_store::RapidStore};
+
+use super::CmdResult;
+
+pub fn quarantine_list(rapid_store: &RapidStore, opts: &DownloadOptions) -> CmdResult {
+    let entries = quarantine::list(rapid_store)?;
+    for entry in entries.iter() {
+        opts.print.event(Event::Info(format!(
+            "{:?} (from {:?} at {}): expected md5 {}, actual {}",
+            entry.quarantined_path,
+            entry.original_path,
+            entry.timestamp,
+            entry.expected_md5,
+            entry.actual_md5.as_deref().unwrap_or("unreadable")
+        )));
+    }
+    opts.print
+        .event(Event::Info(format!("{} quarantined files", entries.len())));
+    Ok(())
+}
+
+pub fn quarantine_purge(rapid_store: &RapidStore, opts: &DownloadOptions) -> CmdResult {
+    let count = quarantine::purge(rapid_store)?;
+    opts.print
+        .event(Event::Info(format!("Deleted {count} quarantined files")));
+    Ok(())
+}
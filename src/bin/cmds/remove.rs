@@ -0,0 +1,21 @@
+use indicatif::HumanBytes;
+
+use sprd::{api::DownloadOptions, event::Event, gc, metadata, rapid::rapid_store::RapidStore};
+
+use super::{CmdError, CmdResult};
+
+pub async fn remove(rapid_store: &RapidStore, opts: &DownloadOptions, name: &str) -> CmdResult {
+    let sdp_md5 = metadata::resolve_sdp_md5(rapid_store, opts, name)
+        .await?
+        .ok_or_else(|| CmdError::NotFound(format!("No such item: {name}")))?;
+
+    let report = gc::remove_sdp(rapid_store, &sdp_md5)?;
+    for path in report.removed_files.iter() {
+        opts.print.event(Event::Info(format!("Removed {path:?}")));
+    }
+    opts.print.event(Event::Info(format!(
+        "Removed {name}, reclaiming {}",
+        HumanBytes(report.reclaimed_bytes)
+    )));
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use std::net::SocketAddr;
+
+use sprd::{api::DownloadOptions, api_server, event::Event, rapid::rapid_store::RapidStore};
+
+use super::CmdResult;
+
+pub async fn serve_api(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    listen: &SocketAddr,
+) -> CmdResult {
+    let (addr, server) = api_server::bind(rapid_store.clone(), listen)?;
+    opts.print
+        .event(Event::Info(format!("Serving metadata on http://{addr}")));
+    server.await?;
+    Ok(())
+}
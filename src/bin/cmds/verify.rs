@@ -1,35 +1,84 @@
 use sprd::{
     api::DownloadOptions,
+    error::Error,
     event::Event,
-    rapid,
-    validation::{self, ValidityErrors},
+    metadata, rapid,
+    validation::{self, SdpValidity},
 };
 
+use super::{CmdError, CmdResult};
+
 pub async fn verify(
     rapid_store: &rapid::rapid_store::RapidStore,
     opts: &DownloadOptions,
     fullname: &str,
-) {
+) -> CmdResult {
     let results = validation::validate_by_fullname(rapid_store, opts, fullname).await;
 
-    match results {
-        Ok(()) => {
-            opts.print
-                .event(Event::Info(format!("Successfully verified {fullname}")));
-        }
-        Err(ValidityErrors::MissingSdp) => {
+    if let Err(Error::Integrity { files }) = &results {
+        for (path, file_error) in files {
             opts.print
-                .event(Event::Error("Sdp file is missing.".to_owned()));
+                .event(Event::Error(format!("{path:?} is {file_error:?}")));
         }
-        Err(ValidityErrors::MetadataQueryError(error)) => {
-            opts.print
-                .event(Event::Error(format!("Metadata query error: {error:?}")));
-        }
-        Err(ValidityErrors::InvalidFiles { files }) => {
-            for (path, file_error) in files {
+    }
+    results?;
+
+    opts.print
+        .event(Event::Info(format!("Successfully verified {fullname}")));
+    Ok(())
+}
+
+pub async fn verify_all(
+    rapid_store: &rapid::rapid_store::RapidStore,
+    opts: &DownloadOptions,
+) -> CmdResult {
+    let results = validation::validate_all(rapid_store, opts).await?;
+
+    let mut invalid = 0;
+    for SdpValidity { sdp_md5, result } in results.iter() {
+        let name = display_name(rapid_store, sdp_md5).await;
+        match result {
+            Ok(()) => {
                 opts.print
-                    .event(Event::Error(format!("{path:?} is {file_error:?}")));
+                    .event(Event::Info(format!("Successfully verified {name}")));
+            }
+            Err(Error::Integrity { files }) => {
+                invalid += 1;
+                opts.print.event(Event::Error(format!(
+                    "{name} has {} invalid files",
+                    files.len()
+                )));
+                for (path, file_error) in files {
+                    opts.print
+                        .event(Event::Error(format!("  {path:?} is {file_error:?}")));
+                }
+            }
+            Err(Error::NotFound(_)) => {
+                invalid += 1;
+                opts.print
+                    .event(Event::Error(format!("{name}: sdp file is missing.")));
+            }
+            Err(error) => {
+                invalid += 1;
+                opts.print.event(Event::Error(format!("{name}: {error}")));
             }
         }
     }
+
+    opts.print.event(Event::Info(format!(
+        "Verified {} sdps, {invalid} invalid",
+        results.len()
+    )));
+    if invalid > 0 {
+        return Err(CmdError::Corrupt(format!("{invalid} invalid sdps")));
+    }
+    Ok(())
+}
+
+/// Archive name and md5 if the sdp can be found in the local metadata, just the md5 otherwise.
+pub async fn display_name(rapid_store: &rapid::rapid_store::RapidStore, sdp_md5: &str) -> String {
+    match metadata::query_metadata_by_sdp_md5(rapid_store, sdp_md5).await {
+        Ok(Some((_, sdp))) => format!("{} ({sdp_md5})", sdp.archive_name),
+        _ => sdp_md5.to_owned(),
+    }
 }
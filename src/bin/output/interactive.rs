@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use sprd::event::{Event, Print};
 
 pub struct InteractiveOutput {
@@ -45,6 +45,29 @@ impl Print for InteractiveOutput {
                     pb.finish_with_message("downloaded");
                 }
             }
+            Event::DiskUsage(usage) => {
+                for archive in usage.archives.iter() {
+                    println!(
+                        "{:>12} {:>12} unique  {} {}",
+                        HumanBytes(archive.size).to_string(),
+                        HumanBytes(archive.unique_size).to_string(),
+                        archive.sdp_md5,
+                        archive.archive_name.as_deref().unwrap_or("")
+                    );
+                }
+                println!("Pool size: {}", HumanBytes(usage.pool_size));
+                println!("Deduplication ratio: {:.2}", usage.dedup_ratio);
+            }
+            Event::MetadataSourceAnswered(source) => {
+                println!("Metadata from {source}");
+            }
+            Event::Finished { error: None, .. } => {}
+            Event::Finished {
+                exit_code,
+                error: Some(error),
+            } => {
+                println!("Error: {error} (exit code {exit_code})");
+            }
             _ => {
                 println!("Event: {event:?}")
             }
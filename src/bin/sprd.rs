@@ -1,14 +1,18 @@
 #![warn(clippy::all)]
 #![warn(rust_2018_idioms)]
 
-use std::{path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
 
 use clap::{Parser, Subcommand, ValueEnum};
+use cmds::{CmdError, CmdResult};
 use output::{interactive::InteractiveOutput, json::JsonOutput};
 use sprd::{
     api::{DownloadOptions, MetadataSource},
-    event::{PrintOutput, SilentOutput},
-    rapid,
+    archive_download::ArchiveType,
+    config::{Config, Origin, Setting},
+    event::{Event, Print, PrintOutput, SilentOutput},
+    lock::{self, LockMode},
+    rapid::rapid_store::RapidStore,
 };
 
 use atty::Stream;
@@ -22,8 +26,27 @@ struct Args {
     #[clap(short, long)] // parse(from_os_str) needed?
     root: Option<PathBuf>,
 
-    #[clap(short, long, value_enum, default_value_t = OutputType::Auto)]
-    output: OutputType,
+    /// Defaults to auto
+    #[clap(short, long, value_enum)]
+    output: Option<OutputType>,
+
+    /// Seconds to wait for other processes using the root folder. Defaults to 60
+    #[clap(long)]
+    lock_timeout: Option<u64>,
+
+    /// Where to look up metadata: local, file, rest=<url>, or a comma separated chain of them.
+    /// Defaults to local for commands that don't download, file otherwise
+    #[clap(long)]
+    metadata_source: Option<MetadataSource>,
+
+    /// Never access the network. Implies --metadata-source local unless set
+    #[clap(long)]
+    offline: bool,
+
+    /// Root shared with other roots: its valid pool files are linked instead of downloaded,
+    /// and downloaded files are added to it
+    #[clap(long)]
+    cache_root: Option<PathBuf>,
 
     #[clap(subcommand)]
     command: Commands,
@@ -42,6 +65,17 @@ enum OutputType {
 enum Commands {
     /// Download the specified (rapid) resource
     Download { rapid_name: String },
+    /// Download a packed archive (.sd7, .sdz) over HTTP into the maps or games folder
+    DownloadFile {
+        url: String,
+        #[clap(long = "type")]
+        archive_type: ArchiveType,
+        /// Expected md5 of the file
+        #[clap(long)]
+        md5: Option<String>,
+    },
+    /// Find a map by name with the springfiles search API and download it into the maps folder
+    DownloadMap { name: String },
 
     /// Download the registry file
     MetaDownloadRegistry,
@@ -51,24 +85,143 @@ enum Commands {
     MetaDownloadSdp { sdp: String },
 
     /// Check if fully downloaded
-    CheckExists { rapid_name: String },
+    CheckExists {
+        rapid_name: String,
+        /// Verify all files, even those unchanged since the last verification
+        #[clap(long)]
+        full: bool,
+    },
     /// Check if fully downloaded & valid
-    Verify { rapid_name: String },
+    Verify {
+        #[clap(required_unless_present = "all")]
+        rapid_name: Option<String>,
+        /// Verify every downloaded sdp
+        #[clap(long, conflicts_with = "rapid_name")]
+        all: bool,
+        /// Verify all files, even those unchanged since the last verification
+        #[clap(long)]
+        full: bool,
+    },
     /// Verify and fix any corruption
-    Fix { rapid_name: String },
+    Fix {
+        #[clap(required_unless_present = "all")]
+        rapid_name: Option<String>,
+        /// Fix every downloaded sdp
+        #[clap(long, conflicts_with = "rapid_name")]
+        all: bool,
+        /// Move corrupt files to the quarantine folder instead of overwriting them
+        #[clap(long)]
+        quarantine: bool,
+    },
+    /// Inspect files moved to quarantine by fix
+    Quarantine {
+        #[clap(subcommand)]
+        command: QuarantineCommands,
+    },
+
+    /// Delete the sdp and the pool files only it uses
+    Remove { rapid_name: String },
+    /// Keep only the most recent sdps of each repo
+    Prune {
+        /// Number of sdps to keep per repo
+        #[clap(long)]
+        keep: usize,
+        /// Only prune this repo
+        #[clap(long)]
+        repo: Option<String>,
+        /// Never remove this sdp (can be repeated)
+        #[clap(long)]
+        pin: Vec<String>,
+    },
+    /// Report disk usage of the pool and each downloaded sdp
+    Du,
+    /// Delete pool files not used by any downloaded sdp
+    Gc {
+        /// Only list the files that would be deleted
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Serve the REST metadata API from the root folder
+    ServeApi {
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum QuarantineCommands {
+    /// List quarantined files
+    List,
+    /// Delete all quarantined files
+    Purge,
+}
 
-    let rapid_store = match args.root {
-        Some(path) => rapid::rapid_store::RapidStore::new(path),
-        None => rapid::rapid_store::RapidStore::default(),
-    };
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the effective configuration and where each value came from
+    Show,
+}
+
+impl Commands {
+    fn default_metadata_source(&self) -> MetadataSource {
+        match self {
+            Commands::CheckExists { .. }
+            | Commands::Verify { .. }
+            | Commands::Fix { .. }
+            | Commands::Remove { .. }
+            | Commands::Prune { .. } => MetadataSource::Local,
+            _ => MetadataSource::FileApi,
+        }
+    }
+
+    /// None for long-running commands, which would block everyone else
+    fn lock_mode(&self) -> Option<LockMode> {
+        match self {
+            Commands::ServeApi { .. } | Commands::Config { .. } => None,
+            Commands::CheckExists { .. }
+            | Commands::Verify { .. }
+            | Commands::Du
+            | Commands::Quarantine {
+                command: QuarantineCommands::List,
+            } => Some(LockMode::Shared),
+            _ => Some(LockMode::Exclusive),
+        }
+    }
+}
+
+impl Args {
+    /// Command line options take precedence over the config files and environment
+    fn override_config(&self, config: &mut Config) {
+        if let Some(output) = self.output {
+            if let Some(value) = output.to_possible_value() {
+                config.output = Setting::command_line(value.get_name().to_owned());
+            }
+        }
+        if let Some(lock_timeout) = self.lock_timeout {
+            config.lock_timeout = Setting::command_line(Duration::from_secs(lock_timeout));
+        }
+        if let Some(metadata_source) = &self.metadata_source {
+            config.metadata_source = Setting::command_line(metadata_source.clone());
+        }
+        if self.offline {
+            config.offline = Setting::command_line(true);
+        }
+        if let Some(cache_root) = &self.cache_root {
+            config.cache_root = Setting::command_line(Some(cache_root.clone()));
+        }
+    }
+}
 
-    let mut opts = DownloadOptions {
-        print: match args.output {
+impl OutputType {
+    fn print(&self) -> Arc<Box<dyn Print>> {
+        match self {
             OutputType::Silent => Arc::new(Box::new(SilentOutput {})),
             OutputType::Auto => {
                 if atty::is(Stream::Stdout) {
@@ -80,42 +233,139 @@ async fn main() {
             OutputType::Json => Arc::new(Box::new(JsonOutput::new())),
             OutputType::Print => Arc::new(Box::new(PrintOutput {})),
             OutputType::Interactive => Arc::new(Box::new(InteractiveOutput::new())),
-        },
-        ..Default::default()
+        }
+    }
+}
+
+fn load_config(args: &Args) -> Result<(Config, OutputType), CmdError> {
+    let mut config = Config::load(args.root.clone())?;
+    args.override_config(&mut config);
+    let output = OutputType::from_str(&config.output.value, true).map_err(|_| {
+        CmdError::Failure(format!(
+            "invalid output {:?} from {}",
+            config.output.value, config.output.origin
+        ))
+    })?;
+    Ok((config, output))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let config = load_config(&args);
+    let output = match &config {
+        Ok((_, output)) => *output,
+        Err(_) => args.output.unwrap_or(OutputType::Auto),
     };
+    let print = output.print();
 
-    match &args.command {
+    let result = match config {
+        Ok((config, _)) => {
+            let rapid_store = config.rapid_store();
+            let mut opts = DownloadOptions {
+                print: print.clone(),
+                ..config.download_options()
+            };
+            if config.metadata_source.origin == Origin::Default {
+                opts.metadata_source = if opts.offline {
+                    MetadataSource::Local
+                } else {
+                    args.command.default_metadata_source()
+                };
+            }
+
+            match args.command.lock_mode() {
+                Some(mode) => match lock::lock(&rapid_store, &opts, mode).await {
+                    Ok(_lock) => run(&args.command, &config, &rapid_store, &mut opts).await,
+                    Err(err) => Err(CmdError::from(err)),
+                },
+                None => run(&args.command, &config, &rapid_store, &mut opts).await,
+            }
+        }
+        Err(err) => Err(err),
+    };
+
+    let (exit_code, error) = match result {
+        Ok(()) => (ExitCode::SUCCESS, None),
+        Err(err) => (err.exit_code(), Some(err)),
+    };
+    print.event(Event::Finished {
+        exit_code: error.as_ref().map_or(0, CmdError::code),
+        error: error.map(|err| err.to_string()),
+    });
+
+    exit_code
+}
+
+async fn run(
+    command: &Commands,
+    config: &Config,
+    rapid_store: &RapidStore,
+    opts: &mut DownloadOptions,
+) -> CmdResult {
+    match command {
         Commands::Download {
             rapid_name: fullname,
-        } => {
-            cmds::download(&rapid_store, &opts, fullname).await;
-        }
-        Commands::MetaDownloadSdp { sdp } => {
-            cmds::meta_download_sdp(&rapid_store, &opts, sdp).await;
-        }
-        Commands::MetaDownloadRegistry => {
-            cmds::meta_download_registry(&rapid_store, &opts).await;
+        } => cmds::download(rapid_store, opts, fullname).await,
+        Commands::DownloadFile {
+            url,
+            archive_type,
+            md5,
+        } => cmds::download_file(rapid_store, opts, url, *archive_type, md5.as_deref()).await,
+        Commands::DownloadMap { name } => {
+            cmds::download_map(rapid_store, opts, &config.content_resolver(), name).await
         }
+        Commands::MetaDownloadSdp { sdp } => cmds::meta_download_sdp(rapid_store, opts, sdp).await,
+        Commands::MetaDownloadRegistry => cmds::meta_download_registry(rapid_store, opts).await,
         Commands::MetaDownloadRepo { rapid_repo: repo } => {
-            cmds::meta_download_repo(&rapid_store, &opts, repo.as_deref()).await;
+            cmds::meta_download_repo(rapid_store, opts, repo.as_deref()).await
         }
 
         Commands::CheckExists {
             rapid_name: fullname,
+            full,
         } => {
-            cmds::check_exists(&rapid_store, fullname);
+            opts.use_verification_cache = !full;
+            cmds::check_exists(rapid_store, opts, fullname).await
         }
         Commands::Verify {
             rapid_name: fullname,
+            all,
+            full,
         } => {
-            opts.metadata_source = MetadataSource::Local;
-            cmds::verify(&rapid_store, &opts, fullname).await;
+            opts.use_verification_cache = !full;
+            match fullname {
+                Some(fullname) if !all => cmds::verify(rapid_store, opts, fullname).await,
+                _ => cmds::verify_all(rapid_store, opts).await,
+            }
         }
         Commands::Fix {
             rapid_name: fullname,
+            all,
+            quarantine,
         } => {
-            opts.metadata_source = MetadataSource::Local;
-            cmds::fix(&rapid_store, &opts, fullname).await;
+            opts.quarantine = *quarantine;
+            match fullname {
+                Some(fullname) if !all => cmds::fix(rapid_store, opts, fullname).await,
+                _ => cmds::fix_all(rapid_store, opts).await,
+            }
+        }
+        Commands::Quarantine { command } => match command {
+            QuarantineCommands::List => cmds::quarantine_list(rapid_store, opts),
+            QuarantineCommands::Purge => cmds::quarantine_purge(rapid_store, opts),
+        },
+        Commands::Remove {
+            rapid_name: fullname,
+        } => cmds::remove(rapid_store, opts, fullname).await,
+        Commands::Prune { keep, repo, pin } => {
+            cmds::prune(rapid_store, opts, *keep, repo.as_deref(), pin).await
         }
+        Commands::Du => cmds::du(rapid_store, opts),
+        Commands::Gc { dry_run } => cmds::gc(rapid_store, opts, *dry_run),
+        Commands::Config {
+            command: ConfigCommands::Show,
+        } => cmds::config_show(config, opts),
+        Commands::ServeApi { listen } => cmds::serve_api(rapid_store, opts, listen).await,
     }
 }
@@ -0,0 +1,109 @@
+//! Synchronous wrappers of the async API, for callers that don't run a tokio runtime.
+//!
+//! Each call runs on its own current-thread runtime, so these functions must not be called
+//! from within an async context.
+
+use std::future::Future;
+
+use crate::{
+    api::DownloadOptions,
+    error::Error,
+    metadata,
+    rapid::{
+        rapid_store::RapidStore,
+        types::{Repo, Sdp},
+    },
+    rapid_download::{self, DownloadReport},
+    validation::{self, SdpValidity},
+};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create tokio runtime")
+        .block_on(future)
+}
+
+/// See [`rapid_download::download`].
+pub fn download(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> Result<DownloadReport, Error> {
+    block_on(rapid_download::download(rapid_store, opts, fullname))
+}
+
+/// See [`validation::validate_by_fullname`].
+pub fn validate_by_fullname(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> Result<(), Error> {
+    block_on(validation::validate_by_fullname(
+        rapid_store,
+        opts,
+        fullname,
+    ))
+}
+
+/// See [`validation::validate_all`].
+pub fn validate_all(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+) -> Result<Vec<SdpValidity>, Error> {
+    block_on(validation::validate_all(rapid_store, opts))
+}
+
+/// See [`metadata::query_metadata`].
+pub fn query_metadata(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    block_on(metadata::query_metadata(rapid_store, opts, fullname))
+}
+
+/// Downloaded sdps, in md5 order. Sdps that no local repo lists are skipped.
+pub fn list(rapid_store: &RapidStore) -> Result<Vec<Sdp>, Error> {
+    block_on(async {
+        let mut sdps = Vec::new();
+        for sdp_path in rapid_store.list_all_sdp_paths()? {
+            let Some(md5) = sdp_path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if let Some((_, sdp)) = metadata::query_metadata_by_sdp_md5(rapid_store, md5).await? {
+                sdps.push(sdp);
+            }
+        }
+        Ok(sdps)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::MetadataSource;
+
+    #[test]
+    fn blocking_api() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::new(MetadataSource::Local);
+        let md5 =
+            test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+
+        let listed = list(&rapid_store).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].md5, md5);
+
+        let (_, sdp) = query_metadata(&rapid_store, &opts, "test:1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdp.archive_name, "Test 1");
+
+        validate_by_fullname(&rapid_store, &opts, "test:1").unwrap();
+        let report = download(&rapid_store, &opts, "test:1").unwrap();
+        assert_eq!(report.files_requested, 0);
+    }
+}
@@ -0,0 +1,594 @@
+//! Settings loaded from, in increasing priority: defaults, the user config
+//! (`<config dir>/sprd/config.toml`), the root config (`<root>/sprd.toml`) and `SPRD_*` environment
+//! variables. Command line options are applied on top by the caller.
+//!
+//! Both files use the same keys, e.g.:
+//!
+//! ```toml
+//! registry_url = "https://repos.springrts.com/repos.gz"
+//! metadata_source = "rest=http://localhost:8080,file"
+//! retry_attempts = 3
+//!
+//! [[repos]]
+//! name = "mygame"
+//! url = "https://example.com/mygame"
+//! ```
+//!
+//! The environment variable of a key is `SPRD_` followed by the key in uppercase, with repos
+//! written as `name=url,name=url` and data dirs separated like `PATH`.
+//!
+//! Spring's own `SPRING_WRITEDIR` and `SPRING_DATADIR` set the root and data dirs with the lowest
+//! priority, so sprd shares the engine's content without any configuration.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{
+    api::{DownloadOptions, MetadataSource, RetryPolicy},
+    content_resolver::{SpringfilesResolver, DEFAULT_SPRINGFILES_URL},
+    error::Error,
+    rapid::{rapid_store::RapidStore, types::Repo},
+};
+
+/// Name of the config file inside a root
+pub const ROOT_CONFIG_FILE: &str = "sprd.toml";
+
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("sprd").join("config.toml"))
+}
+
+/// Where the value of a setting came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    UserConfig(PathBuf),
+    RootConfig(PathBuf),
+    Env(String),
+    CommandLine,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::UserConfig(path) => write!(f, "user config {}", path.display()),
+            Origin::RootConfig(path) => write!(f, "root config {}", path.display()),
+            Origin::Env(var) => write!(f, "environment variable {var}"),
+            Origin::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+impl<T> Setting<T> {
+    fn default(value: T) -> Self {
+        Setting {
+            value,
+            origin: Origin::Default,
+        }
+    }
+
+    pub fn command_line(value: T) -> Self {
+        Setting {
+            value,
+            origin: Origin::CommandLine,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub root: Setting<PathBuf>,
+    /// Read-only dirs searched after the root
+    pub data_dirs: Setting<Vec<PathBuf>>,
+    pub registry_url: Setting<String>,
+    /// Repos that aren't in the registry
+    pub repos: Setting<Vec<Repo>>,
+    pub metadata_source: Setting<MetadataSource>,
+    pub verify_concurrency: Setting<usize>,
+    pub retry_attempts: Setting<u32>,
+    pub retry_delay: Setting<Duration>,
+    /// HTTP response timeout, none if unset
+    pub timeout: Setting<Option<Duration>>,
+    pub lock_timeout: Setting<Duration>,
+    /// Output format of the command line tool
+    pub output: Setting<String>,
+    pub offline: Setting<bool>,
+    /// Root to share pool files with, see [`DownloadOptions::cache_root`]
+    pub cache_root: Setting<Option<PathBuf>>,
+    /// Search API used to find maps by name
+    pub springfiles_url: Setting<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let opts = DownloadOptions::default();
+        Config {
+            root: Setting::default(RapidStore::default().root),
+            data_dirs: Setting::default(Vec::new()),
+            registry_url: Setting::default(opts.registry_url),
+            repos: Setting::default(opts.extra_repos),
+            metadata_source: Setting::default(opts.metadata_source),
+            verify_concurrency: Setting::default(opts.verify_concurrency),
+            retry_attempts: Setting::default(opts.retry.attempts),
+            retry_delay: Setting::default(opts.retry.delay),
+            timeout: Setting::default(opts.timeout),
+            lock_timeout: Setting::default(opts.lock_timeout),
+            output: Setting::default("auto".to_owned()),
+            offline: Setting::default(opts.offline),
+            cache_root: Setting::default(opts.cache_root),
+            springfiles_url: Setting::default(DEFAULT_SPRINGFILES_URL.to_owned()),
+        }
+    }
+}
+
+/// Contents of a config file, or of the environment variables.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    root: Option<PathBuf>,
+    data_dirs: Option<Vec<PathBuf>>,
+    registry_url: Option<String>,
+    repos: Option<Vec<RepoEntry>>,
+    metadata_source: Option<String>,
+    verify_concurrency: Option<usize>,
+    retry_attempts: Option<u32>,
+    /// Seconds
+    retry_delay: Option<u64>,
+    /// Seconds
+    timeout: Option<u64>,
+    /// Seconds
+    lock_timeout: Option<u64>,
+    output: Option<String>,
+    offline: Option<bool>,
+    cache_root: Option<PathBuf>,
+    springfiles_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RepoEntry {
+    name: String,
+    url: String,
+}
+
+impl Config {
+    /// Loads the config files and environment variables. `root` is the root given on the
+    /// command line, if any, which decides the root config to read.
+    pub fn load(root: Option<PathBuf>) -> Result<Config, Error> {
+        Config::load_from(
+            user_config_path().as_deref(),
+            |var| std::env::var(var).ok(),
+            root,
+        )
+    }
+
+    pub fn load_from(
+        user_config: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+        root: Option<PathBuf>,
+    ) -> Result<Config, Error> {
+        let mut config = Config::default();
+        if let Some(write_dir) = env("SPRING_WRITEDIR").filter(|dir| !dir.is_empty()) {
+            config.root = Setting {
+                value: PathBuf::from(write_dir),
+                origin: Origin::Env("SPRING_WRITEDIR".to_owned()),
+            };
+        }
+        if let Some(data_dirs) = env("SPRING_DATADIR") {
+            config.data_dirs = Setting {
+                value: split_paths(&data_dirs),
+                origin: Origin::Env("SPRING_DATADIR".to_owned()),
+            };
+        }
+
+        if let Some(path) = user_config.filter(|path| path.exists()) {
+            config.apply(read_config_file(path)?, |_| {
+                Origin::UserConfig(path.to_owned())
+            })?;
+        }
+
+        let env_config = read_env(&env)?;
+        // The root decides which root config is read, so it has to be known before the rest
+        if let Some(env_root) = &env_config.root {
+            config.root = Setting {
+                value: env_root.clone(),
+                origin: Origin::Env(env_var("root")),
+            };
+        }
+        if let Some(root) = &root {
+            config.root = Setting::command_line(root.clone());
+        }
+
+        let root_config_path = config.root.value.join(ROOT_CONFIG_FILE);
+        if root_config_path.exists() {
+            let root_config = read_config_file(&root_config_path)?;
+            if root_config.root.is_some() {
+                return Err(Error::Config {
+                    origin: Origin::RootConfig(root_config_path).to_string(),
+                    source: anyhow::anyhow!("the root can't be set from the root config"),
+                });
+            }
+            config.apply(root_config, |_| {
+                Origin::RootConfig(root_config_path.clone())
+            })?;
+        }
+
+        config.apply(env_config, |key| Origin::Env(env_var(key)))?;
+        if let Some(root) = root {
+            config.root = Setting::command_line(root);
+        }
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, file: ConfigFile, origin: impl Fn(&str) -> Origin) -> Result<(), Error> {
+        fn set<T>(setting: &mut Setting<T>, value: Option<T>, origin: Origin) {
+            if let Some(value) = value {
+                *setting = Setting { value, origin };
+            }
+        }
+
+        let metadata_source = file
+            .metadata_source
+            .map(|source| source.parse::<MetadataSource>())
+            .transpose()
+            .map_err(|e| Error::Config {
+                origin: origin("metadata_source").to_string(),
+                source: anyhow::anyhow!(e),
+            })?;
+        let repos = file.repos.map(|repos| {
+            repos
+                .into_iter()
+                .map(|repo| Repo {
+                    name: repo.name,
+                    url: repo.url,
+                })
+                .collect()
+        });
+
+        set(&mut self.root, file.root, origin("root"));
+        set(&mut self.data_dirs, file.data_dirs, origin("data_dirs"));
+        set(
+            &mut self.registry_url,
+            file.registry_url,
+            origin("registry_url"),
+        );
+        set(&mut self.repos, repos, origin("repos"));
+        set(
+            &mut self.metadata_source,
+            metadata_source,
+            origin("metadata_source"),
+        );
+        set(
+            &mut self.verify_concurrency,
+            file.verify_concurrency,
+            origin("verify_concurrency"),
+        );
+        set(
+            &mut self.retry_attempts,
+            file.retry_attempts,
+            origin("retry_attempts"),
+        );
+        set(
+            &mut self.retry_delay,
+            file.retry_delay.map(Duration::from_secs),
+            origin("retry_delay"),
+        );
+        set(
+            &mut self.timeout,
+            file.timeout
+                .map(|timeout| Some(Duration::from_secs(timeout))),
+            origin("timeout"),
+        );
+        set(
+            &mut self.lock_timeout,
+            file.lock_timeout.map(Duration::from_secs),
+            origin("lock_timeout"),
+        );
+        set(&mut self.output, file.output, origin("output"));
+        set(&mut self.offline, file.offline, origin("offline"));
+        set(
+            &mut self.cache_root,
+            file.cache_root.map(Some),
+            origin("cache_root"),
+        );
+        set(
+            &mut self.springfiles_url,
+            file.springfiles_url,
+            origin("springfiles_url"),
+        );
+
+        Ok(())
+    }
+
+    pub fn rapid_store(&self) -> RapidStore {
+        RapidStore::new(self.root.value.clone()).with_data_dirs(self.data_dirs.value.clone())
+    }
+
+    pub fn content_resolver(&self) -> SpringfilesResolver {
+        SpringfilesResolver {
+            url: self.springfiles_url.value.clone(),
+        }
+    }
+
+    /// Options with every setting applied, printing nothing.
+    pub fn download_options(&self) -> DownloadOptions {
+        DownloadOptions {
+            metadata_source: self.metadata_source.value.clone(),
+            lock_timeout: self.lock_timeout.value,
+            verify_concurrency: self.verify_concurrency.value,
+            offline: self.offline.value,
+            registry_url: self.registry_url.value.clone(),
+            extra_repos: self.repos.value.clone(),
+            retry: RetryPolicy {
+                attempts: self.retry_attempts.value.max(1),
+                delay: self.retry_delay.value,
+            },
+            timeout: self.timeout.value,
+            cache_root: self.cache_root.value.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Key, value and origin of each setting, in the format of the config file.
+    pub fn entries(&self) -> Vec<(&'static str, String, &Origin)> {
+        fn entry<'a, T>(
+            key: &'static str,
+            setting: &'a Setting<T>,
+            format: impl Fn(&T) -> String,
+        ) -> (&'static str, String, &'a Origin) {
+            (key, format(&setting.value), &setting.origin)
+        }
+        let quoted = |s: &String| format!("{s:?}");
+        let seconds = |d: &Duration| d.as_secs().to_string();
+
+        vec![
+            entry("root", &self.root, |root| {
+                format!("{:?}", root.display().to_string())
+            }),
+            entry("data_dirs", &self.data_dirs, |dirs| {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|dir| format!("{:?}", dir.display().to_string()))
+                    .collect();
+                format!("[{}]", dirs.join(", "))
+            }),
+            entry("registry_url", &self.registry_url, quoted),
+            entry("repos", &self.repos, |repos| {
+                let repos: Vec<String> = repos
+                    .iter()
+                    .map(|repo| format!("{{ name = {:?}, url = {:?} }}", repo.name, repo.url))
+                    .collect();
+                format!("[{}]", repos.join(", "))
+            }),
+            entry("metadata_source", &self.metadata_source, |source| {
+                format!("{:?}", source.to_string())
+            }),
+            entry(
+                "verify_concurrency",
+                &self.verify_concurrency,
+                usize::to_string,
+            ),
+            entry("retry_attempts", &self.retry_attempts, u32::to_string),
+            entry("retry_delay", &self.retry_delay, seconds),
+            entry("timeout", &self.timeout, |timeout| match timeout {
+                Some(timeout) => seconds(timeout),
+                None => "none".to_owned(),
+            }),
+            entry("lock_timeout", &self.lock_timeout, seconds),
+            entry("output", &self.output, quoted),
+            entry("offline", &self.offline, bool::to_string),
+            entry(
+                "cache_root",
+                &self.cache_root,
+                |cache_root| match cache_root {
+                    Some(cache_root) => format!("{:?}", cache_root.display().to_string()),
+                    None => "none".to_owned(),
+                },
+            ),
+            entry("springfiles_url", &self.springfiles_url, quoted),
+        ]
+    }
+}
+
+/// Splits a `PATH`-like list, `:` separated on unix and `;` on windows.
+fn split_paths(paths: &str) -> Vec<PathBuf> {
+    std::env::split_paths(paths)
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect()
+}
+
+fn env_var(key: &str) -> String {
+    format!("SPRD_{}", key.to_uppercase())
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, Error> {
+    let contents = fs::read_to_string(path).map_err(|e| Error::filesystem(path, e))?;
+    toml::from_str(&contents).map_err(|e| Error::Config {
+        origin: path.display().to_string(),
+        source: e.into(),
+    })
+}
+
+fn read_env(env: &impl Fn(&str) -> Option<String>) -> Result<ConfigFile, Error> {
+    fn parse<T: std::str::FromStr>(
+        env: &impl Fn(&str) -> Option<String>,
+        key: &str,
+    ) -> Result<Option<T>, Error>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        env(&env_var(key))
+            .map(|value| value.parse::<T>())
+            .transpose()
+            .map_err(|e| Error::Config {
+                origin: Origin::Env(env_var(key)).to_string(),
+                source: e.into(),
+            })
+    }
+
+    let repos = env(&env_var("repos"))
+        .map(|repos| {
+            repos
+                .split(',')
+                .filter(|repo| !repo.is_empty())
+                .map(|repo| match repo.split_once('=') {
+                    Some((name, url)) => Ok(RepoEntry {
+                        name: name.trim().to_owned(),
+                        url: url.trim().to_owned(),
+                    }),
+                    None => Err(Error::Config {
+                        origin: Origin::Env(env_var("repos")).to_string(),
+                        source: anyhow::anyhow!("expected name=url, got {repo:?}"),
+                    }),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    Ok(ConfigFile {
+        root: env(&env_var("root")).map(PathBuf::from),
+        data_dirs: env(&env_var("data_dirs")).map(|dirs| split_paths(&dirs)),
+        registry_url: env(&env_var("registry_url")),
+        repos,
+        metadata_source: env(&env_var("metadata_source")),
+        verify_concurrency: parse(env, "verify_concurrency")?,
+        retry_attempts: parse(env, "retry_attempts")?,
+        retry_delay: parse(env, "retry_delay")?,
+        timeout: parse(env, "timeout")?,
+        lock_timeout: parse(env, "lock_timeout")?,
+        output: env(&env_var("output")),
+        offline: parse(env, "offline")?,
+        cache_root: env(&env_var("cache_root")).map(PathBuf::from),
+        springfiles_url: env(&env_var("springfiles_url")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_config = dir.path().join("config.toml");
+        let root = dir.path().join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            &user_config,
+            r#"
+            root = "ignored because of the command line"
+            registry_url = "https://user.example.com/repos.gz"
+            retry_attempts = 2
+            output = "json"
+
+            [[repos]]
+            name = "mygame"
+            url = "https://example.com/mygame"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            root.join(ROOT_CONFIG_FILE),
+            "retry_attempts = 5\nmetadata_source = \"local\"\n",
+        )
+        .unwrap();
+        let env = HashMap::from([("SPRD_RETRY_ATTEMPTS", "7"), ("SPRD_OFFLINE", "true")]);
+
+        let config = Config::load_from(
+            Some(&user_config),
+            |var| env.get(var).map(|value| value.to_string()),
+            Some(root.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(config.root, Setting::command_line(root.clone()));
+        assert_eq!(
+            config.registry_url.origin,
+            Origin::UserConfig(user_config.clone())
+        );
+        assert_eq!(config.repos.value[0].name, "mygame");
+        assert_eq!(
+            config.metadata_source,
+            Setting {
+                value: MetadataSource::Local,
+                origin: Origin::RootConfig(root.join(ROOT_CONFIG_FILE)),
+            }
+        );
+        assert_eq!(
+            config.retry_attempts,
+            Setting {
+                value: 7,
+                origin: Origin::Env("SPRD_RETRY_ATTEMPTS".to_owned()),
+            }
+        );
+        assert!(config.offline.value);
+        assert_eq!(config.lock_timeout.origin, Origin::Default);
+
+        let opts = config.download_options();
+        assert_eq!(opts.retry.attempts, 7);
+        assert_eq!(opts.registry_url, "https://user.example.com/repos.gz");
+    }
+
+    #[test]
+    fn spring_dirs_have_the_lowest_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let write_dir = dir.path().join("write");
+        let data_dirs = std::env::join_paths([dir.path().join("a"), dir.path().join("b")]).unwrap();
+        let mut env = HashMap::from([
+            ("SPRING_WRITEDIR", write_dir.display().to_string()),
+            ("SPRING_DATADIR", data_dirs.to_string_lossy().into_owned()),
+        ]);
+
+        let config = Config::load_from(None, |var| env.get(var).cloned(), None).unwrap();
+        assert_eq!(
+            config.root,
+            Setting {
+                value: write_dir.clone(),
+                origin: Origin::Env("SPRING_WRITEDIR".to_owned()),
+            }
+        );
+        assert_eq!(
+            config.data_dirs.value,
+            vec![dir.path().join("a"), dir.path().join("b")]
+        );
+        assert_eq!(config.rapid_store().data_dirs, config.data_dirs.value);
+
+        env.insert("SPRD_ROOT", dir.path().display().to_string());
+        env.insert("SPRD_DATA_DIRS", String::new());
+        let config = Config::load_from(None, |var| env.get(var).cloned(), None).unwrap();
+        assert_eq!(config.root.value, dir.path());
+        assert!(config.data_dirs.value.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_config = dir.path().join("config.toml");
+        fs::write(&user_config, "retry_atempts = 2\n").unwrap();
+        assert!(matches!(
+            Config::load_from(Some(&user_config), |_| None, Some(dir.path().to_owned())),
+            Err(Error::Config { .. })
+        ));
+
+        let err = Config::load_from(
+            None,
+            |var| (var == "SPRD_METADATA_SOURCE").then(|| "ftp".to_owned()),
+            Some(dir.path().to_owned()),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("SPRD_METADATA_SOURCE"), "{err}");
+    }
+}
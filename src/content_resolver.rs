@@ -0,0 +1,318 @@
+//! Finding maps and games by name, for content that isn't distributed through rapid.
+
+use std::future::Future;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+
+use crate::{
+    api::DownloadOptions,
+    archive_download::{self, ArchiveReport, ArchiveType},
+    error::Error,
+    event::Event,
+    http_download::check_online,
+    metadata::metadata_rest::get_json_with_opts,
+    rapid::rapid_store::RapidStore,
+};
+
+pub const DEFAULT_SPRINGFILES_URL: &str = "https://springfiles.springrts.com/json.php";
+
+/// An archive found by a [`ContentResolver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedArchive {
+    pub name: String,
+    pub md5: Option<String>,
+    /// URLs of the file, best first
+    pub mirrors: Vec<String>,
+}
+
+/// Looks up where to download an archive from.
+pub trait ContentResolver {
+    /// None if the resolver doesn't know the archive.
+    fn resolve(
+        &self,
+        opts: &DownloadOptions,
+        name: &str,
+        archive_type: ArchiveType,
+    ) -> impl Future<Output = Result<Option<ResolvedArchive>, Error>>;
+}
+
+/// Resolver for the springfiles JSON search API (`json.php?springname=...&category=...`).
+pub struct SpringfilesResolver {
+    pub url: String,
+}
+
+impl Default for SpringfilesResolver {
+    fn default() -> Self {
+        SpringfilesResolver {
+            url: DEFAULT_SPRINGFILES_URL.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SpringfilesResult {
+    springname: String,
+    #[serde(default)]
+    md5: Option<String>,
+    #[serde(default)]
+    mirrors: Vec<String>,
+}
+
+impl ContentResolver for SpringfilesResolver {
+    async fn resolve(
+        &self,
+        opts: &DownloadOptions,
+        name: &str,
+        archive_type: ArchiveType,
+    ) -> Result<Option<ResolvedArchive>, Error> {
+        let url = format!(
+            "{}?springname={}&category={archive_type}",
+            self.url,
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        );
+        check_online(opts, &url)?;
+        let Some(results) = get_json_with_opts::<Vec<SpringfilesResult>>(opts, &url).await? else {
+            return Ok(None);
+        };
+
+        // The search is case insensitive and may return other versions, which must not be
+        // downloaded in place of the one asked for
+        let Some(result) = results
+            .iter()
+            .find(|result| result.springname == name)
+            .or_else(|| {
+                results
+                    .iter()
+                    .find(|result| result.springname.eq_ignore_ascii_case(name))
+            })
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(ResolvedArchive {
+            name: result.springname.clone(),
+            md5: result.md5.clone(),
+            mirrors: rank_mirrors(&result.mirrors),
+        }))
+    }
+}
+
+/// Https mirrors first, otherwise in the order given.
+fn rank_mirrors(mirrors: &[String]) -> Vec<String> {
+    let mut ranked = mirrors.to_vec();
+    ranked.sort_by_key(|mirror| !mirror.starts_with("https://"));
+    ranked
+}
+
+/// Resolves `name` and downloads it into the folder of `archive_type`, trying the mirrors in
+/// order until one gives a valid file.
+pub async fn download_by_name(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    resolver: &impl ContentResolver,
+    name: &str,
+    archive_type: ArchiveType,
+) -> Result<ArchiveReport, Error> {
+    let archive = resolver
+        .resolve(opts, name, archive_type)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("{archive_type} {name}")))?;
+    opts.print.event(Event::Info(format!(
+        "Resolved {name} to {} ({} mirrors)",
+        archive.name,
+        archive.mirrors.len()
+    )));
+
+    let mut last_error = None;
+    for mirror in archive.mirrors.iter() {
+        match archive_download::download_archive(
+            rapid_store,
+            opts,
+            mirror,
+            archive_type,
+            archive.md5.as_deref(),
+        )
+        .await
+        {
+            // A broken mirror doesn't mean the others are
+            Err(err @ (Error::Network { .. } | Error::Integrity { .. })) => {
+                opts.print.event(Event::Info(format!(
+                    "Mirror {mirror} failed, trying the next one: {err}"
+                )));
+                last_error = Some(err);
+            }
+            result => return result,
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| Error::NotFound(format!("mirror of {}", archive.name))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use md5::{Digest, Md5};
+
+    use super::*;
+
+    const MAP: &[u8] = b"map contents";
+
+    fn springfiles(files_server: &str, md5: &str) -> String {
+        test_utils::serve_files(vec![(
+            "/json.php?springname=Test%20Map&category=map".to_owned(),
+            format!(
+                r#"[
+                    {{"springname": "Test Map v2", "md5": "{md5}", "mirrors": []}},
+                    {{
+                        "springname": "Test Map",
+                        "md5": "{md5}",
+                        "mirrors": ["{files_server}/missing/test_map.sd7", "{files_server}/maps/test_map.sd7"]
+                    }}
+                ]"#
+            )
+            .into_bytes(),
+        )]) + "/json.php"
+    }
+
+    #[test]
+    fn https_mirrors_first() {
+        let mirrors = ["http://a", "https://b", "http://c", "https://d"].map(String::from);
+        assert_eq!(
+            rank_mirrors(&mirrors),
+            ["https://b", "https://d", "http://a", "http://c"]
+        );
+    }
+
+    #[tokio::test]
+    async fn download_map_by_name() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::default();
+        let files_server =
+            test_utils::serve_files(vec![("/maps/test_map.sd7".to_owned(), MAP.to_vec())]);
+        let md5 = format!("{:x}", Md5::digest(MAP));
+        let resolver = SpringfilesResolver {
+            url: springfiles(&files_server, &md5),
+        };
+
+        let archive = resolver
+            .resolve(&opts, "Test Map", ArchiveType::Map)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(archive.name, "Test Map");
+        assert_eq!(archive.mirrors.len(), 2);
+
+        // The first mirror doesn't have the file
+        let report = download_by_name(&rapid_store, &opts, &resolver, "Test Map", ArchiveType::Map)
+            .await
+            .unwrap();
+        assert_eq!(report.path, root.path().join("maps/test_map.sd7"));
+        assert_eq!(fs::read(&report.path).unwrap(), MAP);
+
+        let err = download_by_name(&rapid_store, &opts, &resolver, "Other", ArchiveType::Map)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn other_names_are_not_downloaded() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::default();
+        let server = test_utils::serve_files(vec![
+            (
+                "/json.php?springname=Test%20Map&category=map".to_owned(),
+                br#"[{"springname": "Test Map v2", "mirrors": ["http://127.0.0.1:1/v2.sd7"]}]"#
+                    .to_vec(),
+            ),
+            (
+                "/json.php?springname=test%20map&category=map".to_owned(),
+                br#"[{"springname": "Test Map", "mirrors": []}]"#.to_vec(),
+            ),
+        ]);
+        let resolver = SpringfilesResolver {
+            url: server + "/json.php",
+        };
+
+        assert_eq!(
+            resolver
+                .resolve(&opts, "Test Map", ArchiveType::Map)
+                .await
+                .unwrap(),
+            None
+        );
+        let err = download_by_name(&rapid_store, &opts, &resolver, "Test Map", ArchiveType::Map)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{err:?}");
+
+        let archive = resolver
+            .resolve(&opts, "test map", ArchiveType::Map)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(archive.name, "Test Map");
+    }
+
+    #[tokio::test]
+    async fn searches_time_out_and_are_retried() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                sockets.push(socket);
+            }
+        });
+        let opts = DownloadOptions {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            retry: crate::api::RetryPolicy {
+                attempts: 3,
+                delay: std::time::Duration::from_millis(10),
+            },
+            ..Default::default()
+        };
+        let resolver = SpringfilesResolver {
+            url: format!("http://{addr}/json.php"),
+        };
+
+        let err = resolver
+            .resolve(&opts, "Test Map", ArchiveType::Map)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+        assert_eq!(connections.load(std::sync::atomic::Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn corrupt_mirrors_are_skipped() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let files_server = test_utils::serve_files(vec![
+            ("/missing/test_map.sd7".to_owned(), b"corrupt".to_vec()),
+            ("/maps/test_map.sd7".to_owned(), MAP.to_vec()),
+        ]);
+        let resolver = SpringfilesResolver {
+            url: springfiles(&files_server, &format!("{:x}", Md5::digest(MAP))),
+        };
+
+        let report = download_by_name(
+            &rapid_store,
+            &DownloadOptions::default(),
+            &resolver,
+            "Test Map",
+            ArchiveType::Map,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&report.path).unwrap(), MAP);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    rapid::{parsing, rapid_store::RapidStore},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveUsage {
+    pub sdp_md5: String,
+    pub archive_name: Option<String>,
+    /// Size of all pool files the archive uses
+    pub size: u64,
+    /// Size that would be freed by removing the archive
+    pub unique_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// Size of all files in the pool, including unreferenced ones
+    pub pool_size: u64,
+    pub archives: Vec<ArchiveUsage>,
+    /// Sum of archive sizes divided by the size of the files they reference
+    pub dedup_ratio: f64,
+}
+
+/// Computes pool usage of each sdp downloaded into the root. Sizes are of the (compressed) files on
+/// disk. Sdps of the data dirs count as references, as they do when removing an archive.
+pub fn disk_usage(rapid_store: &RapidStore) -> Result<DiskUsage, Error> {
+    let archive_names = archive_names(rapid_store);
+    let root_sdp_paths: HashSet<PathBuf> = rapid_store.list_sdp_paths()?.into_iter().collect();
+
+    let mut archive_files: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    let mut references: HashMap<PathBuf, usize> = HashMap::new();
+    for sdp_path in rapid_store.list_all_sdp_paths()? {
+        let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path)?;
+        let mut pool_paths: Vec<PathBuf> = sdp_packages
+            .iter()
+            .map(|package| rapid_store.get_pool_path(package))
+            .collect();
+        pool_paths.sort();
+        pool_paths.dedup();
+        for pool_path in pool_paths.iter() {
+            *references.entry(pool_path.clone()).or_default() += 1;
+        }
+        if !root_sdp_paths.contains(&sdp_path) {
+            continue;
+        }
+
+        let sdp_md5 = sdp_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        archive_files.push((sdp_md5, pool_paths));
+    }
+
+    let file_size = |path: &PathBuf| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let referenced: HashSet<&PathBuf> = archive_files
+        .iter()
+        .flat_map(|(_, pool_paths)| pool_paths)
+        .collect();
+    let referenced_size: u64 = referenced.into_iter().map(file_size).sum();
+
+    let archives: Vec<ArchiveUsage> = archive_files
+        .into_iter()
+        .map(|(sdp_md5, pool_paths)| ArchiveUsage {
+            archive_name: archive_names.get(&sdp_md5).cloned(),
+            size: pool_paths.iter().map(file_size).sum(),
+            unique_size: pool_paths
+                .iter()
+                .filter(|path| references[*path] == 1)
+                .map(file_size)
+                .sum(),
+            sdp_md5,
+        })
+        .collect();
+
+    let total_archive_size: u64 = archives.iter().map(|archive| archive.size).sum();
+    let dedup_ratio = if referenced_size == 0 {
+        1.0
+    } else {
+        total_archive_size as f64 / referenced_size as f64
+    };
+
+    Ok(DiskUsage {
+        pool_size: pool_size(rapid_store)?,
+        archives,
+        dedup_ratio,
+    })
+}
+
+fn pool_size(rapid_store: &RapidStore) -> Result<u64, Error> {
+    let pool_dir = rapid_store.get_pool_dir();
+    if !pool_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut size = 0;
+    for dir in fs::read_dir(&pool_dir).map_err(|e| Error::filesystem(&pool_dir, e))? {
+        let dir = dir.map_err(|e| Error::filesystem(&pool_dir, e))?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&dir).map_err(|e| Error::filesystem(&dir, e))? {
+            let metadata = file
+                .and_then(|file| file.metadata())
+                .map_err(|e| Error::filesystem(&dir, e))?;
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Archive names by sdp md5, from whichever repo metadata is available locally.
+fn archive_names(rapid_store: &RapidStore) -> HashMap<String, String> {
+    let repos =
+        parsing::parse_repos_from_file(&rapid_store.find_registry_path()).unwrap_or_default();
+
+    repos
+        .iter()
+        .filter_map(|repo| parsing::read_rapid_from_file(&rapid_store.find_repo_path(repo)).ok())
+        .flatten()
+        .map(|sdp| (sdp.md5, sdp.archive_name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_files_are_not_unique() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let first = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared"), ("b.lua", b"first")],
+        );
+        test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:2",
+            "Test 2",
+            &[("a.lua", b"shared"), ("b.lua", b"second")],
+        );
+        let size_of = |contents: &[u8]| {
+            fs::metadata(test_utils::pool_path(root.path(), contents))
+                .unwrap()
+                .len()
+        };
+
+        let usage = disk_usage(&rapid_store).unwrap();
+        assert_eq!(
+            usage.pool_size,
+            size_of(b"shared") + size_of(b"first") + size_of(b"second")
+        );
+        let archive = usage
+            .archives
+            .iter()
+            .find(|archive| archive.sdp_md5 == first)
+            .unwrap();
+        assert_eq!(archive.archive_name.as_deref(), Some("Test 1"));
+        assert_eq!(archive.size, size_of(b"shared") + size_of(b"first"));
+        assert_eq!(archive.unique_size, size_of(b"first"));
+        assert!(usage.dedup_ratio > 1.0);
+    }
+
+    #[test]
+    fn data_dir_sdps_are_references() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store =
+            RapidStore::new(root.path().to_owned()).with_data_dirs([data_dir.path().to_owned()]);
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared"), ("b.lua", b"first")],
+        );
+        test_utils::add_local_archive(
+            data_dir.path(),
+            "test",
+            "test:2",
+            "Test 2",
+            &[("a.lua", b"shared")],
+        );
+
+        let usage = disk_usage(&rapid_store).unwrap();
+        assert_eq!(usage.archives.len(), 1);
+        assert_eq!(usage.archives[0].sdp_md5, md5);
+        assert_eq!(
+            usage.archives[0].unique_size,
+            fs::metadata(test_utils::pool_path(root.path(), b"first"))
+                .unwrap()
+                .len()
+        );
+
+        // Removing the archive keeps the file the data dir sdp references too
+        let removed = crate::gc::remove_sdp(&rapid_store, &md5).unwrap();
+        assert_eq!(
+            removed.removed_files,
+            vec![
+                rapid_store.get_sdp_path_from_md5(&md5),
+                test_utils::pool_path(root.path(), b"first")
+            ]
+        );
+    }
+}
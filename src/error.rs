@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::validation::FileError;
+
+/// Error returned by all public functions of the library.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The requested repo, sdp or archive doesn't exist
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    /// A registry, versions or sdp file can't be read or parsed
+    #[error("invalid metadata file {path:?}")]
+    Metadata {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error("network error for {url}{}", status.map(|s| format!(" (HTTP status {s})")).unwrap_or_default())]
+    Network {
+        url: String,
+        /// HTTP status of the response, if one was received
+        status: Option<u16>,
+        #[source]
+        source: Option<anyhow::Error>,
+    },
+
+    #[error("filesystem error for {path:?}")]
+    Filesystem {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Pool files are missing, corrupt or don't match the md5 from the sdp
+    #[error("{} invalid files", files.len())]
+    Integrity { files: Vec<(PathBuf, FileError)> },
+
+    #[error("timed out waiting for lock on {0:?}, another process is using the folder")]
+    Locked(PathBuf),
+
+    /// A config file or environment variable is invalid
+    #[error("invalid configuration in {origin}")]
+    Config {
+        origin: String,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Whether the same request might succeed later: connection failures, timeouts,
+    /// 5xx and 429 responses.
+    pub fn is_retryable(&self) -> bool {
+        let Error::Network { status, source, .. } = self else {
+            return false;
+        };
+        match status {
+            Some(status) => *status >= 500 || *status == 429,
+            None => source.as_ref().is_some_and(|source| {
+                source.chain().any(|cause| {
+                    if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                        e.is_connect() || e.is_timeout()
+                    } else if let Some(e) = cause.downcast_ref::<hyper::Error>() {
+                        e.is_connect() || e.is_closed() || e.is_incomplete_message()
+                    } else {
+                        cause.is::<std::io::Error>() || cause.is::<tokio::time::error::Elapsed>()
+                    }
+                })
+            }),
+        }
+    }
+
+    /// A missing file is [`Error::NotFound`] rather than invalid: nothing was downloaded yet.
+    pub(crate) fn metadata(path: &Path, source: impl Into<anyhow::Error>) -> Self {
+        let source = source.into();
+        let missing = source.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+        });
+        if missing {
+            return Error::NotFound(path.display().to_string());
+        }
+
+        Error::Metadata {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub(crate) fn network(url: impl ToString, source: impl Into<anyhow::Error>) -> Self {
+        Error::Network {
+            url: url.to_string(),
+            status: None,
+            source: Some(source.into()),
+        }
+    }
+
+    pub(crate) fn filesystem(path: &Path, source: std::io::Error) -> Self {
+        Error::Filesystem {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::disk_usage::DiskUsage;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Event {
     Info(String),
@@ -8,6 +10,14 @@ pub enum Event {
     DownloadProgress(usize),
     DownloadFinished,
     DownloadFailed,
+    DiskUsage(DiskUsage),
+    /// Source of a chained [`MetadataSource`](crate::api::MetadataSource) that answered a query
+    MetadataSourceAnswered(String),
+    /// Last event of a command. The exit code is 0 on success.
+    Finished {
+        exit_code: u8,
+        error: Option<String>,
+    },
 }
 
 pub trait Print {
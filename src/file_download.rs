@@ -4,7 +4,6 @@ use std::path;
 use std::str::FromStr;
 
 use hyper::body::HttpBody;
-use thiserror::Error;
 // use tokio::fs::File;
 // use tokio::io::AsyncWriteExt;
 
@@ -15,34 +14,19 @@ use super::rapid::{
 };
 use crate::{
     api::DownloadOptions,
+    error::Error,
     event::Event,
-    http_download::{http_download_with_url, ResponseWithSize},
+    http_download::{http_download_with_url, http_download_with_url_allow_http, ResponseWithSize},
 };
 
-#[derive(Debug, Error)]
-pub enum FileDownloadError {
-    #[error("invalid Uri")]
-    InvalidUri(#[source] hyper::http::uri::InvalidUri),
-
-    #[error("invalid server response")]
-    InvalidServerResponse(#[source] anyhow::Error),
-
-    #[error("filesystem error")]
-    FilesystemError(#[source] anyhow::Error),
-}
-
-#[derive(Debug, Error)]
-#[error("No parent folder")]
-struct NoParentFolder {}
-
 pub async fn download_sdp(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo: &Repo,
     sdp: &Sdp,
-) -> Result<(), FileDownloadError> {
+) -> Result<(), Error> {
     let url = format!("{}/packages/{}.sdp", repo.url, sdp.md5);
-    let url = hyper::Uri::from_str(&url).map_err(FileDownloadError::InvalidUri)?;
+    let url = hyper::Uri::from_str(&url).map_err(|e| Error::network(&url, e))?;
     let dest = rapid_store.get_sdp_path_from_md5(&sdp.md5);
     download_file(opts, url, &dest, "Downloading SDP").await
 }
@@ -50,10 +34,9 @@ pub async fn download_sdp(
 pub async fn download_all_repos(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
-) -> Result<(), FileDownloadError> {
+) -> Result<(), Error> {
     let registry_file = rapid_store.get_registry_path();
-    let repos = parse_repos_from_file(&registry_file)
-        .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+    let repos = parse_repos_from_file(&registry_file)?;
     for repo in repos {
         download_repo(rapid_store, opts, &repo).await?;
     }
@@ -65,19 +48,20 @@ pub async fn download_repo(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo: &Repo,
-) -> Result<(), FileDownloadError> {
+) -> Result<(), Error> {
     let repo_file = rapid_store.get_repo_path(repo);
     let versions_url = repo.url.to_owned() + "/versions.gz";
 
-    let url = hyper::Uri::from_str(&versions_url).map_err(FileDownloadError::InvalidUri)?;
+    let url = hyper::Uri::from_str(&versions_url).map_err(|e| Error::network(&versions_url, e))?;
     download_file(opts, url, &repo_file, "Downloading repository").await
 }
 
 pub async fn download_repo_registry(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
-) -> Result<(), FileDownloadError> {
-    let url = hyper::Uri::from_static("https://repos.springrts.com/repos.gz");
+) -> Result<(), Error> {
+    let url = hyper::Uri::from_str(&opts.registry_url)
+        .map_err(|e| Error::network(&opts.registry_url, e))?;
     let registry_file = rapid_store.get_registry_path();
     download_file(opts, url, &registry_file, "Downloading registry").await
 }
@@ -87,29 +71,51 @@ pub async fn download_file(
     url: hyper::Uri,
     dest: &path::Path,
     title: &str,
-) -> Result<(), FileDownloadError> {
-    let ResponseWithSize { mut res, size } = http_download_with_url(url).await?;
+) -> Result<(), Error> {
+    let url_string = url.to_string();
+    let response = http_download_with_url(opts, url).await?;
+    write_response(opts, &url_string, response, dest, title).await
+}
+
+/// Like [`download_file`], but plain http URLs are allowed too, see
+/// [`http_download_with_url_allow_http`].
+pub async fn download_file_allow_http(
+    opts: &DownloadOptions,
+    url: hyper::Uri,
+    dest: &path::Path,
+    title: &str,
+) -> Result<(), Error> {
+    let url_string = url.to_string();
+    let response = http_download_with_url_allow_http(opts, url).await?;
+    write_response(opts, &url_string, response, dest, title).await
+}
 
+async fn write_response(
+    opts: &DownloadOptions,
+    url_string: &str,
+    ResponseWithSize { mut res, size }: ResponseWithSize,
+    dest: &path::Path,
+    title: &str,
+) -> Result<(), Error> {
     let mut downloaded_size = 0;
-    std::fs::create_dir_all(dest.parent().ok_or_else(|| {
-        FileDownloadError::FilesystemError(anyhow::Error::new(NoParentFolder {}))
-    })?)
-    .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::filesystem(parent, e))?;
+    }
     // let mut file = File::create(dest).await?;
-    let mut file = File::create(dest).map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+    let mut file = File::create(dest).map_err(|e| Error::filesystem(dest, e))?;
 
     opts.print
         .event(Event::Info(format!("Downloading {title}")));
     opts.print.event(Event::DownloadStarted(size));
 
     while let Some(next) = res.data().await {
-        let chunk = next.map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+        let chunk = next.map_err(|e| Error::network(url_string, e))?;
         downloaded_size += chunk.len();
         opts.print.event(Event::DownloadProgress(downloaded_size));
 
         // file.write_all(&chunk).await?;
         file.write_all(&chunk)
-            .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+            .map_err(|e| Error::filesystem(dest, e))?;
     }
     opts.print.event(Event::DownloadFinished);
 
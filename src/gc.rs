@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::Error,
+    rapid::{parsing, rapid_store::RapidStore},
+};
+
+/// Extension used for partially written files.
+pub const TEMP_EXTENSION: &str = "tmp";
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub unreferenced_files: Vec<PathBuf>,
+    pub temp_files: Vec<PathBuf>,
+    pub reclaimed_bytes: u64,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct RemoveReport {
+    pub removed_files: Vec<PathBuf>,
+    pub reclaimed_bytes: u64,
+}
+
+/// Pool paths referenced by the given sdp files.
+pub fn referenced_pool_paths(
+    rapid_store: &RapidStore,
+    sdp_paths: &[PathBuf],
+) -> Result<HashSet<PathBuf>, Error> {
+    let mut referenced = HashSet::new();
+    for sdp_path in sdp_paths {
+        let sdp_packages = parsing::load_sdp_packages_from_file(sdp_path)?;
+        referenced.extend(
+            sdp_packages
+                .iter()
+                .map(|package| rapid_store.get_pool_path(package)),
+        );
+    }
+
+    Ok(referenced)
+}
+
+/// Deletes pool files that aren't referenced by any installed sdp, as well as leftover temp files.
+/// Only the root is cleaned, but sdps in the data dirs count as references too.
+///
+/// Aborts without deleting anything if any sdp can't be read, as its files would look unreferenced.
+pub fn collect_garbage(rapid_store: &RapidStore, dry_run: bool) -> Result<GcReport, Error> {
+    let sdp_paths = rapid_store.list_all_sdp_paths()?;
+    let referenced = referenced_pool_paths(rapid_store, &sdp_paths)?;
+
+    let mut report = GcReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for path in list_files(&rapid_store.get_pool_dir(), 2)? {
+        if is_temp_file(&path) {
+            report.temp_files.push(path);
+        } else if !referenced.contains(&path) {
+            report.unreferenced_files.push(path);
+        }
+    }
+    report.temp_files.extend(
+        list_files(&rapid_store.get_packages_dir(), 1)?
+            .into_iter()
+            .filter(|path| is_temp_file(path)),
+    );
+
+    for path in report
+        .unreferenced_files
+        .iter()
+        .chain(report.temp_files.iter())
+    {
+        report.reclaimed_bytes += remove_file(path, dry_run)?;
+    }
+
+    Ok(report)
+}
+
+/// Deletes the sdp and those of its pool files that no other downloaded sdp references.
+pub fn remove_sdp(rapid_store: &RapidStore, sdp_md5: &str) -> Result<RemoveReport, Error> {
+    let sdp_path = rapid_store.get_sdp_path_from_md5(sdp_md5);
+    if !sdp_path.exists() {
+        return Err(Error::NotFound(format!("sdp {sdp_md5}")));
+    }
+    let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path)?;
+
+    let other_sdp_paths: Vec<PathBuf> = rapid_store
+        .list_all_sdp_paths()?
+        .into_iter()
+        .filter(|path| *path != sdp_path)
+        .collect();
+    let referenced = referenced_pool_paths(rapid_store, &other_sdp_paths)?;
+
+    let mut report = RemoveReport {
+        reclaimed_bytes: remove_file(&sdp_path, false)?,
+        ..Default::default()
+    };
+    report.removed_files.push(sdp_path);
+
+    for package in sdp_packages.iter() {
+        let pool_path = rapid_store.get_pool_path(package);
+        if referenced.contains(&pool_path) || !pool_path.exists() {
+            continue;
+        }
+        report.reclaimed_bytes += remove_file(&pool_path, false)?;
+        report.removed_files.push(pool_path);
+    }
+
+    Ok(report)
+}
+
+pub(crate) fn remove_file(path: &Path, dry_run: bool) -> Result<u64, Error> {
+    let size = fs::metadata(path)
+        .map_err(|e| Error::filesystem(path, e))?
+        .len();
+    if !dry_run {
+        fs::remove_file(path).map_err(|e| Error::filesystem(path, e))?;
+    }
+
+    Ok(size)
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == TEMP_EXTENSION)
+}
+
+/// Files at most `depth` levels below `dir`, sorted.
+fn list_files(dir: &Path, depth: usize) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    if depth == 0 || !dir.exists() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(dir).map_err(|e| Error::filesystem(dir, e))? {
+        let path = entry.map_err(|e| Error::filesystem(dir, e))?.path();
+        if path.is_dir() {
+            files.extend(list_files(&path, depth - 1)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_only_unreferenced_files() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared"), ("b.lua", b"first")],
+        );
+        let second = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:2",
+            "Test 2",
+            &[("a.lua", b"shared"), ("b.lua", b"second")],
+        );
+        fs::remove_file(rapid_store.get_sdp_path_from_md5(&second)).unwrap();
+        let temp_file = rapid_store.get_packages_dir().join("partial.sdp.tmp");
+        fs::write(&temp_file, b"partial").unwrap();
+
+        let report = collect_garbage(&rapid_store, true).unwrap();
+        assert_eq!(
+            report.unreferenced_files,
+            vec![test_utils::pool_path(root.path(), b"second")]
+        );
+        assert_eq!(report.temp_files, vec![temp_file.clone()]);
+        assert!(report.reclaimed_bytes > 0);
+        assert!(temp_file.exists());
+
+        let report = collect_garbage(&rapid_store, false).unwrap();
+        assert_eq!(report.unreferenced_files.len(), 1);
+        assert!(!temp_file.exists());
+        assert!(!test_utils::pool_path(root.path(), b"second").exists());
+        assert!(test_utils::pool_path(root.path(), b"shared").exists());
+        assert!(test_utils::pool_path(root.path(), b"first").exists());
+
+        let report = collect_garbage(&rapid_store, false).unwrap();
+        assert!(report.unreferenced_files.is_empty());
+        assert_eq!(report.reclaimed_bytes, 0);
+    }
+
+    #[test]
+    fn remove_keeps_shared_files() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let first = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared"), ("b.lua", b"first")],
+        );
+        test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:2",
+            "Test 2",
+            &[("a.lua", b"shared"), ("b.lua", b"second")],
+        );
+
+        let report = remove_sdp(&rapid_store, &first).unwrap();
+        assert_eq!(
+            report.removed_files,
+            vec![
+                rapid_store.get_sdp_path_from_md5(&first),
+                test_utils::pool_path(root.path(), b"first")
+            ]
+        );
+        assert!(test_utils::pool_path(root.path(), b"shared").exists());
+        assert!(test_utils::pool_path(root.path(), b"second").exists());
+
+        assert!(matches!(
+            remove_sdp(&rapid_store, &first),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn data_dir_sdps_keep_root_files() {
+        let root = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        let rapid_store =
+            RapidStore::new(root.path().to_owned()).with_data_dirs([shared.path().to_owned()]);
+        // A file of the shared archive that was downloaded to the root
+        test_utils::add_local_archive(
+            shared.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared")],
+        );
+        let root_copy = test_utils::pool_path(root.path(), b"shared");
+        fs::create_dir_all(root_copy.parent().unwrap()).unwrap();
+        fs::copy(test_utils::pool_path(shared.path(), b"shared"), &root_copy).unwrap();
+
+        let report = collect_garbage(&rapid_store, false).unwrap();
+        assert!(report.unreferenced_files.is_empty());
+        assert!(root_copy.exists());
+    }
+}
@@ -1,76 +1,151 @@
-use hyper::{Body, Client, Request, Response};
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper::{client::HttpConnector, Body, Client, Request, Response};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use thiserror::Error;
 
-use crate::file_download::FileDownloadError;
+use crate::{api::DownloadOptions, error::Error, event::Event};
 
 #[derive(Debug, Error)]
 #[error("Missing content length")]
 struct MissingContentLength {}
 
+#[derive(Debug, Error)]
+#[error("network access is disabled in offline mode")]
+pub struct Offline {}
+
+/// Fails if `opts` forbid network access. Every outgoing request must be checked first.
+pub fn check_online(opts: &DownloadOptions, url: impl ToString) -> Result<(), Error> {
+    if opts.offline {
+        return Err(Error::network(url, Offline {}));
+    }
+    Ok(())
+}
+
 pub struct ResponseWithSize {
     pub res: Response<Body>,
     pub size: usize,
 }
 
 pub async fn http_download_with_url(
+    opts: &DownloadOptions,
     url: hyper::Uri,
-) -> Result<ResponseWithSize, FileDownloadError> {
-    let https = HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .build();
-    let client = Client::builder().build::<_, Body>(https);
-
-    let res = client
-        .get(url.clone())
-        .await
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
-
-    let total_size = res
-        .headers()
-        .get("content-length")
-        .ok_or_else(|| FileDownloadError::InvalidServerResponse(MissingContentLength {}.into()))?
-        .to_str()
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
-    let total_size = total_size
-        .parse::<i64>()
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+) -> Result<ResponseWithSize, Error> {
+    http_download_with_request(opts, || {
+        Request::get(url.clone())
+            .body(Body::empty())
+            .map_err(|e| Error::network(&url, e))
+    })
+    .await
+}
 
-    Ok(ResponseWithSize {
-        res,
-        size: total_size as usize,
+/// Like [`http_download_with_url`], but plain http URLs are allowed too. Only for archives
+/// hosted outside rapid, whose mirrors are often plain http.
+pub async fn http_download_with_url_allow_http(
+    opts: &DownloadOptions,
+    url: hyper::Uri,
+) -> Result<ResponseWithSize, Error> {
+    send_with_retries(opts, &client(true), || {
+        Request::get(url.clone())
+            .body(Body::empty())
+            .map_err(|e| Error::network(&url, e))
     })
+    .await
 }
 
+/// Sends the request built by `build_request`, building it again for each retry.
 pub async fn http_download_with_request(
-    request: Request<Body>,
-) -> Result<ResponseWithSize, FileDownloadError> {
-    let https = HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .build();
-    let client = Client::builder().build::<_, Body>(https);
-
-    let res = client
-        .request(request)
-        .await
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+    opts: &DownloadOptions,
+    build_request: impl Fn() -> Result<Request<Body>, Error>,
+) -> Result<ResponseWithSize, Error> {
+    send_with_retries(opts, &client(false), build_request).await
+}
+
+fn client(allow_http: bool) -> Client<HttpsConnector<HttpConnector>> {
+    let builder = HttpsConnectorBuilder::new().with_native_roots();
+    let builder = if allow_http {
+        builder.https_or_http()
+    } else {
+        builder.https_only()
+    };
+    Client::builder().build::<_, Body>(builder.enable_http1().build())
+}
+
+async fn send_with_retries(
+    opts: &DownloadOptions,
+    client: &Client<HttpsConnector<HttpConnector>>,
+    build_request: impl Fn() -> Result<Request<Body>, Error>,
+) -> Result<ResponseWithSize, Error> {
+    let mut attempt = 1;
+    loop {
+        let request = build_request()?;
+        let url = request.uri().to_string();
+        check_online(opts, &url)?;
+
+        let response = client.request(request);
+        let result = match opts.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, response).await {
+                Ok(res) => res.map_err(|e| Error::network(&url, e)),
+                Err(elapsed) => Err(Error::network(&url, elapsed)),
+            },
+            None => response.await.map_err(|e| Error::network(&url, e)),
+        }
+        .and_then(|res| response_with_size(&url, res));
+
+        match result {
+            Err(err) if err.is_retryable() && attempt < opts.retry.attempts => {
+                opts.print.event(Event::Info(format!(
+                    "Retrying {url} ({attempt}/{}) after error: {err}",
+                    opts.retry.attempts - 1
+                )));
+                tokio::time::sleep(opts.retry.delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn response_with_size(url: &str, res: Response<Body>) -> Result<ResponseWithSize, Error> {
+    if !res.status().is_success() {
+        return Err(Error::Network {
+            url: url.to_owned(),
+            status: Some(res.status().as_u16()),
+            source: None,
+        });
+    }
 
     let total_size = res
         .headers()
         .get("content-length")
-        .ok_or_else(|| FileDownloadError::InvalidServerResponse(MissingContentLength {}.into()))?
+        .ok_or_else(|| Error::network(url, MissingContentLength {}))?
         .to_str()
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+        .map_err(|e| Error::network(url, e))?;
     let total_size = total_size
         .parse::<i64>()
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+        .map_err(|e| Error::network(url, e))?;
 
     Ok(ResponseWithSize {
         res,
         size: total_size as usize,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn plain_http_only_when_allowed() {
+        let server = test_utils::serve_files(vec![("/file".to_owned(), b"contents".to_vec())]);
+        let url: hyper::Uri = format!("{server}/file").parse().unwrap();
+        let opts = DownloadOptions::default();
+
+        let err = http_download_with_url(&opts, url.clone())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+
+        let response = http_download_with_url_allow_http(&opts, url).await.unwrap();
+        assert_eq!(response.size, 8);
+    }
+}
@@ -1,11 +1,26 @@
 pub mod api;
+pub mod api_server;
+pub mod archive_download;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod config;
+pub mod content_resolver;
+pub mod disk_usage;
+pub mod error;
 pub mod event;
 pub mod file_download;
+pub mod gc;
+pub mod lock;
 pub mod metadata;
+pub mod pool_cache;
 pub mod pool_downloader;
+pub mod prune;
+pub mod quarantine;
 pub mod rapid;
 pub mod rapid_download;
+pub mod repair;
 pub mod validation;
+pub mod verification_cache;
 
 mod gz;
 mod http_download;
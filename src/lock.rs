@@ -0,0 +1,97 @@
+use std::fs::{self, File};
+use std::time::{Duration, Instant};
+
+use crate::{api::DownloadOptions, error::Error, event::Event, rapid::rapid_store::RapidStore};
+use fs2::FileExt;
+
+const LOCK_FILE: &str = "sprd.lock";
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// For operations that only read the root
+    Shared,
+    /// For operations that write or delete files
+    Exclusive,
+}
+
+/// Advisory lock on a rapid root, released when dropped.
+#[derive(Debug)]
+pub struct RootLock {
+    file: File,
+}
+
+impl Drop for RootLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Locks the root, waiting up to `opts.lock_timeout` for other processes to release it.
+pub async fn lock(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    mode: LockMode,
+) -> Result<RootLock, Error> {
+    fs::create_dir_all(&rapid_store.root).map_err(|e| Error::filesystem(&rapid_store.root, e))?;
+    let path = rapid_store.root.join(LOCK_FILE);
+    let file = File::create(&path).map_err(|e| Error::filesystem(&path, e))?;
+
+    let started = Instant::now();
+    loop {
+        let result = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&file),
+        };
+        match result {
+            Ok(()) => return Ok(RootLock { file }),
+            Err(e) if e.kind() != fs2::lock_contended_error().kind() => {
+                return Err(Error::filesystem(&path, e));
+            }
+            Err(_) => {}
+        }
+
+        if started.elapsed() >= opts.lock_timeout {
+            return Err(Error::Locked(path));
+        }
+        if started.elapsed() < RETRY_INTERVAL {
+            opts.print.event(Event::Info(format!(
+                "Waiting for another process to release {path:?}"
+            )));
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exclusive_lock_excludes() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions {
+            lock_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let first = lock(&rapid_store, &opts, LockMode::Shared).await.unwrap();
+        let second = lock(&rapid_store, &opts, LockMode::Shared).await.unwrap();
+        assert!(matches!(
+            lock(&rapid_store, &opts, LockMode::Exclusive).await,
+            Err(Error::Locked(_))
+        ));
+
+        drop(first);
+        drop(second);
+        let exclusive = lock(&rapid_store, &opts, LockMode::Exclusive)
+            .await
+            .unwrap();
+        assert!(matches!(
+            lock(&rapid_store, &opts, LockMode::Shared).await,
+            Err(Error::Locked(_))
+        ));
+        drop(exclusive);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     api::DownloadOptions,
+    error::Error,
     file_download,
     rapid::{
         rapid_store::RapidStore,
@@ -7,19 +8,17 @@ use crate::{
     },
 };
 
-use super::{metadata_local, MetadataQueryError};
+use super::metadata_local;
 
 pub async fn query_sdp_files(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo: &Repo,
     sdp: &Sdp,
-) -> Result<Vec<SdpPackage>, MetadataQueryError> {
-    let dest_sdp = rapid_store.get_sdp_path(sdp);
+) -> Result<Vec<SdpPackage>, Error> {
+    let dest_sdp = rapid_store.find_sdp_path(sdp);
     if !dest_sdp.exists() {
-        file_download::download_sdp(rapid_store, opts, repo, sdp)
-            .await
-            .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?;
+        file_download::download_sdp(rapid_store, opts, repo, sdp).await?;
     }
     metadata_local::query_sdp_files(rapid_store, sdp).await
 }
@@ -28,7 +27,7 @@ pub async fn query_metadata(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     fullname: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
+) -> Result<Option<(Repo, Sdp)>, Error> {
     let repo_tag = fullname.split(':').collect::<Vec<&str>>();
     let repo_basename = repo_tag[0];
 
@@ -47,11 +46,9 @@ pub async fn query_repo(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo_basename: &str,
-) -> Result<Option<Repo>, MetadataQueryError> {
-    // if !rapid_store.get_registry_path().exists() {
-    file_download::download_repo_registry(rapid_store, opts)
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?;
+) -> Result<Option<Repo>, Error> {
+    // if !rapid_store.find_registry_path().exists() {
+    file_download::download_repo_registry(rapid_store, opts).await?;
     // }
 
     metadata_local::query_repo(rapid_store, repo_basename).await
@@ -62,16 +59,13 @@ pub async fn query_sdp(
     opts: &DownloadOptions,
     repo: &Repo,
     fullname: &str,
-) -> Result<Option<Sdp>, MetadataQueryError> {
-    file_download::download_repo(rapid_store, opts, repo)
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?;
+) -> Result<Option<Sdp>, Error> {
+    file_download::download_repo(rapid_store, opts, repo).await?;
 
     metadata_local::query_sdp(rapid_store, repo, fullname).await
 }
 
 #[cfg(test)]
-
 mod tests {
 
     use crate::rapid;
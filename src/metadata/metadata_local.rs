@@ -5,12 +5,12 @@ use crate::rapid::{
     types::{Repo, Sdp, SdpPackage},
 };
 
-use super::MetadataQueryError;
+use crate::error::Error;
 
 pub async fn query_metadata(
     rapid_store: &RapidStore,
     fullname: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
+) -> Result<Option<(Repo, Sdp)>, Error> {
     let repo_tag = fullname.split(':').collect::<Vec<&str>>();
     if repo_tag.len() >= 2 {
         let result = query_metadata_with_tag(rapid_store, fullname, repo_tag[0]).await?;
@@ -26,10 +26,9 @@ pub async fn query_metadata(
 pub async fn query_metadata_with_name(
     rapid_store: &RapidStore,
     fullname: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
-    let registry_file = rapid_store.get_registry_path();
-    let repos = parse_repos_from_file(&registry_file)
-        .map_err(|e| MetadataQueryError::CorruptFile(e.into()))?;
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    let registry_file = rapid_store.find_registry_path();
+    let repos = parse_repos_from_file(&registry_file)?;
 
     for repo in repos {
         let sdp = query_sdp(rapid_store, &repo, fullname).await;
@@ -49,7 +48,7 @@ pub async fn query_metadata_with_tag(
     rapid_store: &RapidStore,
     fullname: &str,
     repo_basename: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
+) -> Result<Option<(Repo, Sdp)>, Error> {
     let repo = match query_repo(rapid_store, repo_basename).await? {
         None => return Ok(None),
         Some(repo) => repo,
@@ -61,15 +60,34 @@ pub async fn query_metadata_with_tag(
     Ok(Some((repo, sdp)))
 }
 
+pub async fn query_metadata_by_sdp_md5(
+    rapid_store: &RapidStore,
+    sdp_md5: &str,
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    let registry_file = rapid_store.find_registry_path();
+    let repos = parse_repos_from_file(&registry_file)?;
+
+    for repo in repos {
+        let sdps = match rapid::parsing::read_rapid_from_file(&rapid_store.find_repo_path(&repo)) {
+            Err(_) => continue,
+            Ok(sdps) => sdps,
+        };
+        if let Some(sdp) = sdps.into_iter().find(|sdp| sdp.md5 == sdp_md5) {
+            return Ok(Some((repo, sdp)));
+        }
+    }
+
+    Ok(None)
+}
+
 // TODO: Should I just move rapid_store API here?
 // It is doing the same thing basically...
 
 pub async fn query_repo(
     rapid_store: &RapidStore,
     repo_basename: &str,
-) -> Result<Option<Repo>, MetadataQueryError> {
-    let repo_registry = rapid::parsing::parse_repos_from_file(&rapid_store.get_registry_path())
-        .map_err(|e| MetadataQueryError::CorruptFile(e.into()))?;
+) -> Result<Option<Repo>, Error> {
+    let repo_registry = rapid::parsing::parse_repos_from_file(&rapid_store.find_registry_path())?;
 
     Ok(repo_registry.into_iter().find(|r| r.name == repo_basename))
 }
@@ -78,10 +96,8 @@ pub async fn query_sdp(
     rapid_store: &RapidStore,
     repo: &Repo,
     fullname: &str,
-) -> Result<Option<Sdp>, MetadataQueryError> {
-    rapid_store
-        .find_sdp(repo, fullname)
-        .map_err(|e| MetadataQueryError::CorruptFile(e.into()))
+) -> Result<Option<Sdp>, Error> {
+    rapid_store.find_sdp(repo, fullname)
     // return match rapid_store.find_sdp(repo, fullname) {
     //     Err(err) => {
     //         println!(
@@ -97,16 +113,12 @@ pub async fn query_sdp(
 pub async fn query_sdp_files(
     rapid_store: &RapidStore,
     sdp: &Sdp,
-) -> Result<Vec<SdpPackage>, MetadataQueryError> {
-    let dest_sdp = rapid_store.get_sdp_path(sdp);
-    assert!(dest_sdp.exists());
-
+) -> Result<Vec<SdpPackage>, Error> {
+    let dest_sdp = rapid_store.find_sdp_path(sdp);
     rapid::parsing::load_sdp_packages_from_file(&dest_sdp)
-        .map_err(|e| MetadataQueryError::CorruptFile(e.into()))
 }
 
 #[cfg(test)]
-
 mod tests {
 
     use std::{
@@ -1,25 +1,35 @@
-use crate::rapid::types::{Repo, Sdp};
-use serde::{Deserialize, Serialize};
+use crate::{
+    api::DownloadOptions,
+    error::Error,
+    event::Event,
+    rapid::types::{Repo, Sdp, SdpPackage},
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use super::MetadataQueryError;
+/// Everything but the unreserved characters of RFC 3986, so names can contain `:`, spaces, `#`...
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 #[derive(Debug, Serialize, Deserialize)]
-struct RepoResponse {
-    id: i32,
-    name: String,
-    url: String,
+pub(crate) struct RepoResponse {
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    pub(crate) url: String,
 }
 
 pub async fn query_repo(
+    opts: &DownloadOptions,
     server: &str,
     repo_basename: &str,
-) -> Result<Option<Repo>, MetadataQueryError> {
-    let resp = reqwest::get(format!("{server}/repo/{repo_basename}"))
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?
-        .json::<RepoResponse>()
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?;
+) -> Result<Option<Repo>, Error> {
+    let url = endpoint(server, "repo", repo_basename);
+    let Some(resp) = get_json_with_opts::<RepoResponse>(opts, &url).await? else {
+        return Ok(None);
+    };
 
     Ok(Some(Repo {
         name: resp.name,
@@ -28,31 +38,30 @@ pub async fn query_repo(
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-struct RapidResponse {
-    id: i32,
-    repo_id: i32,
-    fullname: String,
-    hash: String,
-    something: String,
-    alias: String,
+pub(crate) struct RapidResponse {
+    pub(crate) id: i32,
+    pub(crate) repo_id: i32,
+    pub(crate) fullname: String,
+    pub(crate) hash: String,
+    pub(crate) something: String,
+    pub(crate) alias: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct SdpResponse {
-    rapid: RapidResponse,
-    repo: RepoResponse,
+pub(crate) struct SdpResponse {
+    pub(crate) rapid: RapidResponse,
+    pub(crate) repo: RepoResponse,
 }
 
 pub async fn query_metadata(
+    opts: &DownloadOptions,
     server: &str,
     fullname: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
-    let resp = reqwest::get(format!("{server}/sdp/{fullname}"))
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?
-        .json::<SdpResponse>()
-        .await
-        .map_err(|e| MetadataQueryError::DownloadFailed(e.into()))?;
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    let url = endpoint(server, "sdp", fullname);
+    let Some(resp) = get_json_with_opts::<SdpResponse>(opts, &url).await? else {
+        return Ok(None);
+    };
 
     let rapid = resp.rapid;
     let repo = resp.repo;
@@ -70,75 +79,324 @@ pub async fn query_metadata(
     )))
 }
 
-pub async fn query_sdp(server: &str, fullname: &str) -> Result<Option<Sdp>, MetadataQueryError> {
-    if let Some(metadata) = query_metadata(server, fullname).await? {
+pub async fn query_sdp(
+    opts: &DownloadOptions,
+    server: &str,
+    fullname: &str,
+) -> Result<Option<Sdp>, Error> {
+    if let Some(metadata) = query_metadata(opts, server, fullname).await? {
         Ok(Some(metadata.1))
     } else {
         Ok(None)
     }
 }
 
-#[cfg(test)]
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct FileResponse {
+    pub(crate) name: String,
+    pub(crate) md5: String,
+    pub(crate) crc32: u32,
+    pub(crate) size: u32,
+}
+
+/// Files of the sdp, or None if the server doesn't know it.
+pub async fn query_sdp_files(
+    opts: &DownloadOptions,
+    server: &str,
+    sdp_md5: &str,
+) -> Result<Option<Vec<SdpPackage>>, Error> {
+    let url = endpoint(server, "files", sdp_md5);
+    let Some(files) = get_json_with_opts::<Vec<FileResponse>>(opts, &url).await? else {
+        return Ok(None);
+    };
+
+    files
+        .into_iter()
+        .map(|file| {
+            let mut package = SdpPackage {
+                name: file.name,
+                crc32: file.crc32.to_le_bytes(),
+                size: file.size,
+                ..Default::default()
+            };
+            // Checked before slicing, a non-ASCII md5 would split a character
+            if file.md5.len() != package.md5.len()
+                || !file.md5.bytes().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(Error::network(
+                    &url,
+                    anyhow::anyhow!("invalid md5 {:?} for {}", file.md5, package.name),
+                ));
+            }
+            package.md5.copy_from_slice(file.md5.as_bytes());
+            for (i, byte) in package.md5_bin.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&file.md5[2 * i..2 * i + 2], 16).map_err(|e| {
+                    Error::network(
+                        &url,
+                        anyhow::anyhow!("invalid md5 for {}: {e}", package.name),
+                    )
+                })?;
+            }
+            Ok(package)
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn endpoint(server: &str, route: &str, name: &str) -> String {
+    format!(
+        "{}/{route}/{}",
+        server.trim_end_matches('/'),
+        utf8_percent_encode(name, PATH_SEGMENT)
+    )
+}
 
+/// None if the server responds with 404. Other error statuses are returned as errors,
+/// retryable for 5xx (see [`Error::is_retryable`]) and retried following `opts.retry`.
+/// `opts.timeout` applies to each request.
+pub(crate) async fn get_json_with_opts<T: DeserializeOwned>(
+    opts: &DownloadOptions,
+    url: &str,
+) -> Result<Option<T>, Error> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = opts.timeout {
+        builder = builder.timeout(timeout);
+    }
+    let client = builder.build().map_err(|e| network_error(url, e))?;
+
+    let mut attempt = 1;
+    loop {
+        match fetch_json(&client, url).await {
+            Err(err) if err.is_retryable() && attempt < opts.retry.attempts => {
+                opts.print.event(Event::Info(format!(
+                    "Retrying {url} ({attempt}/{}) after error: {err}",
+                    opts.retry.attempts - 1
+                )));
+                tokio::time::sleep(opts.retry.delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn fetch_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<Option<T>, Error> {
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| network_error(url, e))?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    resp.error_for_status()
+        .map_err(|e| network_error(url, e))?
+        .json::<T>()
+        .await
+        .map(Some)
+        .map_err(|e| network_error(url, e))
+}
+
+fn network_error(url: &str, e: reqwest::Error) -> Error {
+    Error::Network {
+        url: url.to_owned(),
+        status: e.status().map(|status| status.as_u16()),
+        source: Some(e.into()),
+    }
+}
+
+#[cfg(test)]
 mod tests {
-    const LOCAL_API_SERVER: &str = "http://localhost:8080";
+    use std::path::Path;
 
-    use crate::{api::DownloadOptions, metadata::metadata_file, rapid};
+    use crate::{api_server, metadata::metadata_local, rapid::rapid_store::RapidStore};
 
     use super::*;
 
+    async fn start_server(root: &Path) -> String {
+        let (addr, server) = api_server::bind(
+            RapidStore::new(root.to_owned()),
+            &([127, 0, 0, 1], 0).into(),
+        )
+        .unwrap();
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
     #[tokio::test]
-    #[ignore] // Need to have the local server
-    async fn test_query_repo() {
-        let repo = query_repo(LOCAL_API_SERVER, "byar").await.unwrap().unwrap();
-        assert_eq!(repo.name, "byar");
-        assert_eq!(repo.url, "https://repos.springrts.com/byar");
+    async fn query_repo_from_local_server() {
+        let opts = DownloadOptions::default();
+        let root = tempfile::tempdir().unwrap();
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        let server = start_server(root.path()).await;
+
+        let repo = query_repo(&opts, &server, "test").await.unwrap().unwrap();
+        assert_eq!(repo.name, "test");
+        assert_eq!(repo.url, "https://repos.springrts.com/test");
+    }
 
-        let rapid_store = rapid::rapid_store::RapidStore::default();
+    #[tokio::test]
+    async fn query_metadata_from_local_server() {
+        let opts = DownloadOptions::default();
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        test_utils::add_local_archive(root.path(), "test", "test:2", "Test 2", &[("b", b"b")]);
+        let server = start_server(root.path()).await;
 
-        let repo_with_file =
-            metadata_file::query_repo(&rapid_store, &DownloadOptions::default(), "byar")
+        for name in ["test:2", "Test 2"] {
+            let metadata = query_metadata(&opts, &server, name).await.unwrap();
+            let local = metadata_local::query_metadata(&rapid_store, "test:2")
                 .await
-                .unwrap()
                 .unwrap();
-        assert_eq!(repo, repo_with_file);
+            assert_eq!(metadata, local, "{name}");
+        }
     }
 
     #[tokio::test]
-    #[ignore] // Need to have the local server
-    async fn test_query_sdp() {
-        let sdp = query_sdp(LOCAL_API_SERVER, "sbc:test")
+    async fn query_sdp_files_from_local_server() {
+        let opts = DownloadOptions::default();
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a"), ("b/c", b"c")],
+        );
+        let server = start_server(root.path()).await;
+
+        let sdp_files = query_sdp_files(&opts, &server, &md5)
             .await
             .unwrap()
             .unwrap();
-        assert_eq!(sdp.rapid_name, "sbc:test");
+        let local = parsing_files(&rapid_store, &md5);
+        assert_eq!(sdp_files, local);
+        assert_eq!(
+            query_sdp_files(&opts, &server, &"0".repeat(32))
+                .await
+                .unwrap(),
+            None
+        );
+    }
 
-        let rapid_store = rapid::rapid_store::RapidStore::default();
+    #[tokio::test]
+    async fn invalid_md5s_are_rejected() {
+        let opts = DownloadOptions::default();
+        let md5 = "0".repeat(32);
+        let invalid = [
+            "a".repeat(31),
+            "aé".to_owned() + &"a".repeat(29),
+            "g".repeat(32),
+        ];
+        let server = test_utils::serve_files(
+            invalid
+                .iter()
+                .enumerate()
+                .map(|(i, file_md5)| {
+                    (
+                        format!("/files/{md5}{i}"),
+                        format!(r#"[{{"name": "a", "md5": "{file_md5}", "crc32": 0, "size": 1}}]"#)
+                            .into_bytes(),
+                    )
+                })
+                .collect(),
+        );
 
-        let repo = metadata_file::query_repo(&rapid_store, &DownloadOptions::default(), "sbc")
-            .await
-            .unwrap()
-            .unwrap();
-        let sdp_file =
-            metadata_file::query_sdp(&rapid_store, &DownloadOptions::default(), &repo, "test")
+        for (i, file_md5) in invalid.iter().enumerate() {
+            let err = query_sdp_files(&opts, &server, &format!("{md5}{i}"))
                 .await
-                .unwrap()
-                .unwrap();
+                .unwrap_err();
+            assert!(matches!(err, Error::Network { .. }), "{file_md5}: {err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_names_are_not_found() {
+        let opts = DownloadOptions::default();
+        let root = tempfile::tempdir().unwrap();
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        let server = start_server(root.path()).await;
 
-        assert_eq!(sdp, sdp_file);
+        assert_eq!(query_repo(&opts, &server, "other").await.unwrap(), None);
+        assert_eq!(
+            query_metadata(&opts, &server, "test:2").await.unwrap(),
+            None
+        );
+        assert_eq!(query_sdp(&opts, &server, "other:1").await.unwrap(), None);
     }
 
     #[tokio::test]
-    #[ignore] // Need to have the local server
-    async fn test_query_metadata() {
-        let (_, sdp) = query_metadata(
-            LOCAL_API_SERVER,
-            "sbc:git:860aac5eb5ce292121b741ca8514516777ae14dc",
-        )
-        .await
-        .unwrap()
-        .unwrap();
+    async fn names_are_url_encoded() {
+        let opts = DownloadOptions::default();
+        let root = tempfile::tempdir().unwrap();
+        test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:a b#1?",
+            "Test / 1%",
+            &[("a", b"a")],
+        );
+        let server = start_server(root.path()).await;
+
+        for name in ["test:a b#1?", "Test / 1%"] {
+            let (_, sdp) = query_metadata(&opts, &server, name).await.unwrap().unwrap();
+            assert_eq!(sdp.rapid_name, "test:a b#1?", "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retryable() {
+        // Without a registry the server can't answer anything
+        let root = tempfile::tempdir().unwrap();
+        let server = start_server(root.path()).await;
+        let opts = DownloadOptions::default();
 
-        assert_eq!(sdp.md5, "d80d786597510d1358be3b04a7e9146e");
+        let err = query_repo(&opts, &server, "test").await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Network {
+                    status: Some(500),
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn hung_servers_time_out() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let opts = DownloadOptions {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            retry: crate::api::RetryPolicy {
+                attempts: 2,
+                delay: std::time::Duration::from_millis(10),
+            },
+            ..Default::default()
+        };
+
+        let err = query_metadata(&opts, &server, "test:1").await.unwrap_err();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+        assert!(err.is_retryable());
+    }
+
+    fn parsing_files(rapid_store: &RapidStore, md5: &str) -> Vec<SdpPackage> {
+        crate::rapid::parsing::load_sdp_packages_from_file(&rapid_store.get_sdp_path_from_md5(md5))
+            .unwrap()
     }
 }
@@ -1,9 +1,13 @@
-use thiserror::Error;
+use std::fs;
+use std::future::Future;
 
 use crate::{
     api::{DownloadOptions, MetadataSource},
+    error::Error,
+    event::Event,
     rapid::types::SdpPackage,
     rapid::{
+        parsing,
         rapid_store::RapidStore,
         types::{Repo, Sdp},
     },
@@ -11,45 +15,57 @@ use crate::{
 
 mod metadata_file;
 mod metadata_local;
-mod metadata_rest;
-
-#[derive(Error, Debug)]
-pub enum MetadataQueryError {
-    #[error("corrupt file")]
-    CorruptFile(#[source] anyhow::Error),
-
-    #[error("download failed")]
-    DownloadFailed(#[source] anyhow::Error),
-}
+pub(crate) mod metadata_rest;
 
 pub async fn query_metadata(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     fullname: &str,
-) -> Result<Option<(Repo, Sdp)>, MetadataQueryError> {
-    match &opts.metadata_source {
-        MetadataSource::Local => metadata_local::query_metadata(rapid_store, fullname).await,
-        MetadataSource::FileApi => metadata_file::query_metadata(rapid_store, opts, fullname).await,
-        MetadataSource::RestApi(api_server) => {
-            metadata_rest::query_metadata(api_server, fullname).await
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    if let Some((repo_basename, _)) = fullname.split_once(':') {
+        if let Some(repo) = extra_repo(opts, repo_basename) {
+            let sdp = query_sdp(rapid_store, opts, repo, fullname).await?;
+            return Ok(sdp.map(|sdp| (repo.clone(), sdp)));
         }
     }
+
+    query_sources(opts, |source| async move {
+        match source {
+            MetadataSource::Local => metadata_local::query_metadata(rapid_store, fullname).await,
+            MetadataSource::FileApi => {
+                metadata_file::query_metadata(rapid_store, opts, fullname).await
+            }
+            MetadataSource::RestApi(api_server) => {
+                metadata_rest::query_metadata(opts, api_server, fullname).await
+            }
+            MetadataSource::Chain(_) => unreachable!("chains are flattened"),
+        }
+    })
+    .await
 }
 
 pub async fn query_repo(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     repo_basename: &str,
-) -> Result<Option<Repo>, MetadataQueryError> {
-    match &opts.metadata_source {
-        MetadataSource::Local => metadata_local::query_repo(rapid_store, repo_basename).await,
-        MetadataSource::FileApi => {
-            metadata_file::query_repo(rapid_store, opts, repo_basename).await
-        }
-        MetadataSource::RestApi(api_server) => {
-            metadata_rest::query_repo(api_server, repo_basename).await
-        }
+) -> Result<Option<Repo>, Error> {
+    if let Some(repo) = extra_repo(opts, repo_basename) {
+        return Ok(Some(repo.clone()));
     }
+
+    query_sources(opts, |source| async move {
+        match source {
+            MetadataSource::Local => metadata_local::query_repo(rapid_store, repo_basename).await,
+            MetadataSource::FileApi => {
+                metadata_file::query_repo(rapid_store, opts, repo_basename).await
+            }
+            MetadataSource::RestApi(api_server) => {
+                metadata_rest::query_repo(opts, api_server, repo_basename).await
+            }
+            MetadataSource::Chain(_) => unreachable!("chains are flattened"),
+        }
+    })
+    .await
 }
 
 pub async fn query_sdp(
@@ -57,14 +73,18 @@ pub async fn query_sdp(
     opts: &DownloadOptions,
     repo: &Repo,
     tag: &str,
-) -> Result<Option<Sdp>, MetadataQueryError> {
-    match &opts.metadata_source {
-        MetadataSource::Local => metadata_local::query_sdp(rapid_store, repo, tag).await,
-        MetadataSource::FileApi => metadata_file::query_sdp(rapid_store, opts, repo, tag).await,
-        MetadataSource::RestApi(api_server) => {
-            metadata_rest::query_sdp(api_server, &format!("{}:{}", &repo.name, tag)).await
+) -> Result<Option<Sdp>, Error> {
+    query_sources(opts, |source| async move {
+        match source {
+            MetadataSource::Local => metadata_local::query_sdp(rapid_store, repo, tag).await,
+            MetadataSource::FileApi => metadata_file::query_sdp(rapid_store, opts, repo, tag).await,
+            MetadataSource::RestApi(api_server) => {
+                metadata_rest::query_sdp(opts, api_server, &format!("{}:{}", &repo.name, tag)).await
+            }
+            MetadataSource::Chain(_) => unreachable!("chains are flattened"),
         }
-    }
+    })
+    .await
 }
 
 pub async fn query_sdp_files(
@@ -72,20 +92,124 @@ pub async fn query_sdp_files(
     opts: &DownloadOptions,
     repo: &Repo,
     sdp: &Sdp,
-) -> Result<Vec<SdpPackage>, MetadataQueryError> {
-    match &opts.metadata_source {
-        MetadataSource::Local => metadata_local::query_sdp_files(rapid_store, sdp).await,
-        MetadataSource::FileApi => {
-            metadata_file::query_sdp_files(rapid_store, opts, repo, sdp).await
+) -> Result<Vec<SdpPackage>, Error> {
+    query_sources(opts, |source| async move {
+        match source {
+            MetadataSource::Local => metadata_local::query_sdp_files(rapid_store, sdp).await,
+            MetadataSource::FileApi => {
+                metadata_file::query_sdp_files(rapid_store, opts, repo, sdp).await
+            }
+            MetadataSource::RestApi(api_server) => {
+                if rapid_store.find_sdp_path(sdp).exists() {
+                    return metadata_local::query_sdp_files(rapid_store, sdp).await;
+                }
+                match metadata_rest::query_sdp_files(opts, api_server, &sdp.md5).await? {
+                    Some(sdp_files) => {
+                        save_sdp_packages(rapid_store, sdp, &sdp_files)?;
+                        Ok(sdp_files)
+                    }
+                    // Not every server provides file lists, get the .sdp from the repo instead
+                    None => metadata_file::query_sdp_files(rapid_store, opts, repo, sdp).await,
+                }
+            }
+            MetadataSource::Chain(_) => unreachable!("chains are flattened"),
         }
-        MetadataSource::RestApi(_api_server) => {
-            unimplemented!("Can't query SDP files from the Rest API at this moment.");
+    })
+    .await
+}
+
+fn extra_repo<'a>(opts: &'a DownloadOptions, repo_basename: &str) -> Option<&'a Repo> {
+    opts.extra_repos
+        .iter()
+        .find(|repo| repo.name == repo_basename)
+}
+
+/// Runs `query` on each source of `opts.metadata_source` until one answers.
+///
+/// Only network errors and missing local files move on to the next source; a definitive answer,
+/// including "not found", is returned as is.
+async fn query_sources<'a, T, F, Fut>(opts: &'a DownloadOptions, query: F) -> Result<T, Error>
+where
+    F: Fn(&'a MetadataSource) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let sources = opts.metadata_source.sources();
+    let chained = sources.len() > 1;
+    let mut last_error = None;
+    for source in sources {
+        // The REST client doesn't go through http_download, which checks everything else
+        let result = match source {
+            MetadataSource::RestApi(url) => match crate::http_download::check_online(opts, url) {
+                Ok(()) => query(source).await,
+                Err(err) => Err(err),
+            },
+            _ => query(source).await,
+        };
+        match result {
+            // A missing local file means the source has nothing yet, not that nothing exists
+            Err(err @ (Error::Network { .. } | Error::NotFound(_))) if chained => {
+                opts.print.event(Event::Info(format!(
+                    "Metadata source {source} failed, trying the next one: {err}"
+                )));
+                last_error = Some(err);
+            }
+            result => {
+                if chained {
+                    opts.print
+                        .event(Event::MetadataSourceAnswered(source.to_string()));
+                }
+                return result;
+            }
         }
     }
+
+    Err(last_error.unwrap_or_else(|| Error::NotFound("no metadata source".to_owned())))
 }
 
-#[cfg(test)]
+/// Writes the .sdp for file lists that didn't come from one, so it can be verified later like any other.
+fn save_sdp_packages(
+    rapid_store: &RapidStore,
+    sdp: &Sdp,
+    sdp_files: &[SdpPackage],
+) -> Result<(), Error> {
+    let path = rapid_store.get_sdp_path(sdp);
+    let data = crate::gz::gzip_data(&parsing::sdp_packages_to_bytes(sdp_files))
+        .map_err(|e| Error::metadata(&path, e))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::filesystem(parent, e))?;
+    }
+    fs::write(&path, data).map_err(|e| Error::filesystem(&path, e))
+}
+
+/// Finds the repo and sdp with the given md5 in the local metadata.
+pub async fn query_metadata_by_sdp_md5(
+    rapid_store: &RapidStore,
+    sdp_md5: &str,
+) -> Result<Option<(Repo, Sdp)>, Error> {
+    metadata_local::query_metadata_by_sdp_md5(rapid_store, sdp_md5).await
+}
+
+/// Resolves a rapid name, archive name or sdp md5 to the md5 of the sdp.
+///
+/// A name that can't be resolved is still accepted as md5 if such an sdp has been downloaded.
+pub async fn resolve_sdp_md5(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    name: &str,
+) -> Result<Option<String>, Error> {
+    let query = query_metadata(rapid_store, opts, name).await;
+    if let Ok(Some((_, sdp))) = query {
+        return Ok(Some(sdp.md5));
+    }
+
+    if rapid_store.find_sdp_path_from_md5(name).exists() {
+        return Ok(Some(name.to_owned()));
+    }
+
+    query.map(|_| None)
+}
 
+#[cfg(test)]
 mod tests {
 
     use crate::api;
@@ -106,4 +230,146 @@ mod tests {
 
         assert_eq!(sdp.md5, "d80d786597510d1358be3b04a7e9146e");
     }
+
+    #[tokio::test]
+    async fn test_resolve_sdp_md5() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = api::DownloadOptions::new(MetadataSource::Local);
+        let md5 =
+            test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+
+        for name in ["test:1", "Test 1", md5.as_str()] {
+            let resolved = resolve_sdp_md5(&rapid_store, &opts, name).await.unwrap();
+            assert_eq!(resolved.as_ref(), Some(&md5), "{name}");
+        }
+        assert_eq!(
+            resolve_sdp_md5(&rapid_store, &opts, "test:2")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    struct RecordedEvents(std::sync::Mutex<Vec<String>>);
+
+    impl crate::event::Print for std::sync::Arc<RecordedEvents> {
+        fn event(&self, event: Event) {
+            if let Event::MetadataSourceAnswered(source) = event {
+                self.0.lock().unwrap().push(source);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_on_unavailable_sources() {
+        // The server has no registry, so it fails every query with a 500
+        let empty_root = tempfile::tempdir().unwrap();
+        let (addr, server) = crate::api_server::bind(
+            RapidStore::new(empty_root.path().to_owned()),
+            &([127, 0, 0, 1], 0).into(),
+        )
+        .unwrap();
+        tokio::spawn(server);
+        let failing = MetadataSource::RestApi(format!("http://{addr}"));
+
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 =
+            test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        let events = std::sync::Arc::new(RecordedEvents(Default::default()));
+        let opts = api::DownloadOptions {
+            metadata_source: MetadataSource::Chain(vec![failing.clone(), MetadataSource::Local]),
+            print: std::sync::Arc::new(Box::new(events.clone())),
+            ..Default::default()
+        };
+
+        let (_, sdp) = query_metadata(&rapid_store, &opts, "test:1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdp.md5, md5);
+        assert_eq!(*events.0.lock().unwrap(), vec!["local".to_owned()]);
+
+        // A local "not found" is definitive, the failing server isn't asked
+        let opts = api::DownloadOptions {
+            metadata_source: MetadataSource::Chain(vec![MetadataSource::Local, failing]),
+            ..Default::default()
+        };
+        assert_eq!(
+            query_metadata(&rapid_store, &opts, "test:2").await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_on_missing_local_files() {
+        let served_root = tempfile::tempdir().unwrap();
+        let md5 = test_utils::add_local_archive(
+            served_root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a")],
+        );
+        let (addr, server) = crate::api_server::bind(
+            RapidStore::new(served_root.path().to_owned()),
+            &([127, 0, 0, 1], 0).into(),
+        )
+        .unwrap();
+        tokio::spawn(server);
+
+        // Nothing was ever downloaded into this root, so it has no registry
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = api::DownloadOptions {
+            metadata_source: MetadataSource::Chain(vec![
+                MetadataSource::Local,
+                MetadataSource::RestApi(format!("http://{addr}")),
+            ]),
+            ..Default::default()
+        };
+        let (_, sdp) = query_metadata(&rapid_store, &opts, "test:1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdp.md5, md5);
+
+        let opts = api::DownloadOptions::new(MetadataSource::Local);
+        let err = query_metadata(&rapid_store, &opts, "test:1")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn offline_never_reaches_the_network() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+
+        // The file source always refreshes the registry first
+        let opts = api::DownloadOptions {
+            metadata_source: MetadataSource::FileApi,
+            offline: true,
+            ..Default::default()
+        };
+        let err = query_metadata(&rapid_store, &opts, "other:1")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+
+        let opts = api::DownloadOptions {
+            metadata_source: MetadataSource::Chain(vec![
+                MetadataSource::RestApi("http://127.0.0.1:1".to_owned()),
+                MetadataSource::Local,
+            ]),
+            offline: true,
+            ..Default::default()
+        };
+        assert!(query_metadata(&rapid_store, &opts, "test:1")
+            .await
+            .unwrap()
+            .is_some());
+    }
 }
@@ -0,0 +1,212 @@
+//! Sharing pool files between roots through a cache root (see
+//! [`DownloadOptions::cache_root`](crate::api::DownloadOptions)). Files are linked rather than
+//! copied wherever the filesystem allows it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    error::Error,
+    gc::TEMP_EXTENSION,
+    rapid::{rapid_store::RapidStore, types::SdpPackage},
+    validation::validate_sdp_package_with_path,
+};
+
+/// How a file was put in place by [`link_or_copy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkKind {
+    Hardlink,
+    Reflink,
+    Copy,
+}
+
+/// Makes `dest` a copy of `src`: a hardlink if possible, otherwise a reflink where the filesystem
+/// supports it, otherwise a plain copy (e.g. across filesystems).
+pub fn link_or_copy(src: &Path, dest: &Path) -> Result<LinkKind, Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::filesystem(parent, e))?;
+    }
+    if fs::hard_link(src, dest).is_ok() {
+        return Ok(LinkKind::Hardlink);
+    }
+
+    let temp_path = temp_path(dest);
+    let kind = match reflink_copy::reflink_or_copy(src, &temp_path) {
+        Ok(None) => LinkKind::Reflink,
+        Ok(Some(_)) => LinkKind::Copy,
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(Error::filesystem(dest, err));
+        }
+    };
+    fs::rename(&temp_path, dest).map_err(|e| Error::filesystem(dest, e))?;
+
+    Ok(kind)
+}
+
+/// Sdps are copied rather than linked: they are small, and downloading one again rewrites it in
+/// place.
+fn copy_sdp(src: &Path, dest: &Path) -> Result<(), Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::filesystem(parent, e))?;
+    }
+    let temp_path = temp_path(dest);
+    if let Err(err) = fs::copy(src, &temp_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::filesystem(src, err));
+    }
+    fs::rename(&temp_path, dest).map_err(|e| Error::filesystem(dest, e))
+}
+
+/// A temp file next to `dest` no other process or thread uses, as several roots may fill the
+/// cache at once.
+fn temp_path(dest: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let mut file_name = dest.file_name().unwrap_or_default().to_owned();
+    file_name.push(format!(
+        ".{}-{}.{TEMP_EXTENSION}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    dest.with_file_name(file_name)
+}
+
+/// Links the files requested by `download_map` that the cache has, and valid, into the root and
+/// unsets their bits. Returns the number of files taken from the cache.
+///
+/// Files that can't be linked are left in the map to be downloaded.
+pub fn take_from_cache(
+    rapid_store: &RapidStore,
+    cache: &RapidStore,
+    sdp_files: &[SdpPackage],
+    download_map: &mut [u8],
+) -> usize {
+    let mut taken = 0;
+    for (i, sdp_file) in sdp_files.iter().enumerate() {
+        if download_map[i / 8] & (1 << (i % 8)) == 0 {
+            continue;
+        }
+        let cached = cache.get_pool_path(sdp_file);
+        if validate_sdp_package_with_path(&cached, sdp_file.md5_bin).is_some() {
+            continue;
+        }
+        if link_or_copy(&cached, &rapid_store.get_pool_path(sdp_file)).is_ok() {
+            download_map[i / 8] &= !(1 << (i % 8));
+            taken += 1;
+        }
+    }
+
+    taken
+}
+
+/// Links the given (downloaded and valid) files of the root into the cache, unless it has them,
+/// along with the sdp they belong to so gc in the cache root keeps them.
+pub fn fill_cache<'a>(
+    rapid_store: &RapidStore,
+    cache: &RapidStore,
+    sdp_md5: &str,
+    sdp_files: impl IntoIterator<Item = &'a SdpPackage>,
+) -> Result<usize, Error> {
+    let cached_sdp = cache.get_sdp_path_from_md5(sdp_md5);
+    if !cached_sdp.exists() {
+        copy_sdp(&rapid_store.find_sdp_path_from_md5(sdp_md5), &cached_sdp)?;
+    }
+
+    let mut filled = 0;
+    for sdp_file in sdp_files {
+        let cached = cache.get_pool_path(sdp_file);
+        if cached.exists() {
+            continue;
+        }
+        link_or_copy(&rapid_store.get_pool_path(sdp_file), &cached)?;
+        filled += 1;
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rapid::parsing;
+
+    #[test]
+    fn temp_paths_are_unique() {
+        let dest = Path::new("pool/ab/cdef.gz");
+        let first = temp_path(dest);
+        assert_ne!(first, temp_path(dest));
+        assert_eq!(first.parent(), dest.parent());
+        assert!(first.extension().is_some_and(|ext| ext == TEMP_EXTENSION));
+    }
+
+    #[test]
+    fn files_are_shared_through_the_cache() {
+        let cache_root = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let cache = RapidStore::new(cache_root.path().to_owned());
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 = test_utils::add_local_archive(
+            cache_root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"cached"), ("b", b"corrupt"), ("c", b"missing")],
+        );
+        let sdp_files =
+            parsing::load_sdp_packages_from_file(&cache.get_sdp_path_from_md5(&md5)).unwrap();
+        fs::write(cache.get_pool_path(&sdp_files[1]), b"not gzip").unwrap();
+        fs::remove_file(cache.get_pool_path(&sdp_files[2])).unwrap();
+
+        let mut download_map = rapid_store.get_missing_files_indices(&sdp_files);
+        assert_eq!(download_map, vec![0b111]);
+        assert_eq!(
+            take_from_cache(&rapid_store, &cache, &sdp_files, &mut download_map),
+            1
+        );
+        assert_eq!(download_map, vec![0b110]);
+        assert!(validate_sdp_package_with_path(
+            &rapid_store.get_pool_path(&sdp_files[0]),
+            sdp_files[0].md5_bin
+        )
+        .is_none());
+
+        // As if "missing" had been downloaded
+        let downloaded = test_utils::pool_path(root.path(), b"missing");
+        fs::create_dir_all(downloaded.parent().unwrap()).unwrap();
+        fs::write(&downloaded, b"downloaded").unwrap();
+        assert_eq!(
+            fill_cache(&rapid_store, &cache, &md5, [&sdp_files[0], &sdp_files[2]]).unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read(cache.get_pool_path(&sdp_files[2])).unwrap(),
+            b"downloaded"
+        );
+    }
+
+    #[test]
+    fn gc_keeps_cached_files() {
+        let cache_root = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let cache = RapidStore::new(cache_root.path().to_owned());
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a"), ("b", b"b")],
+        );
+        let sdp_files =
+            parsing::load_sdp_packages_from_file(&rapid_store.get_sdp_path_from_md5(&md5)).unwrap();
+
+        assert_eq!(
+            fill_cache(&rapid_store, &cache, &md5, &sdp_files).unwrap(),
+            2
+        );
+        let report = crate::gc::collect_garbage(&cache, false).unwrap();
+        assert!(report.unreferenced_files.is_empty(), "{report:?}");
+        assert!(cache.get_sdp_path_from_md5(&md5).exists());
+    }
+}
@@ -1,8 +1,8 @@
 use std::array::TryFromSliceError;
 use std::fs::{self, File};
 use std::io::Write;
+use std::path::PathBuf;
 
-use anyhow::Context;
 use hyper::body::HttpBody;
 use hyper::{Body, Request, Response, Uri};
 
@@ -13,10 +13,10 @@ use hyper::{Body, Request, Response, Uri};
 use thiserror::Error;
 
 use crate::api::DownloadOptions;
+use crate::error::Error;
 use crate::event::Event;
-use crate::file_download::FileDownloadError;
 use crate::http_download::{http_download_with_request, ResponseWithSize};
-use crate::validation::validate_sdp_package;
+use crate::validation::{validate_sdp_package, FileError};
 
 use super::gz;
 use super::rapid::{
@@ -24,6 +24,37 @@ use super::rapid::{
     types::{Repo, Sdp, SdpPackage},
 };
 
+/// Problem with a single file found while downloading it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FileIssue {
+    /// The size of the written file differs from the size received from the server
+    SizeMismatch { expected: u64, on_disk: u64 },
+    /// The written file couldn't be inspected, so its size is unknown
+    Unreadable,
+    /// The written file doesn't match the sdp
+    Invalid(FileError),
+}
+
+#[derive(Debug, Default)]
+pub struct PoolDownloadReport {
+    pub files_requested: usize,
+    pub files_written: usize,
+    /// Compressed bytes received from the streamer
+    pub bytes_transferred: usize,
+    pub issues: Vec<(PathBuf, FileIssue)>,
+    /// Bytes left in the stream after the last requested file, should be 0
+    pub trailing_bytes: usize,
+}
+
+impl PoolDownloadReport {
+    /// Number of distinct files with at least one issue
+    pub fn files_failed(&self) -> usize {
+        let mut paths: Vec<&PathBuf> = self.issues.iter().map(|(path, _)| path).collect();
+        paths.dedup();
+        paths.len()
+    }
+}
+
 pub async fn download_sdp_files(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
@@ -31,11 +62,11 @@ pub async fn download_sdp_files(
     sdp: &Sdp,
     download_map: Vec<u8>,
     sdp_files: &[SdpPackage],
-) -> Result<(), FileDownloadError> {
+) -> Result<PoolDownloadReport, Error> {
     let url = format!("{}/streamer.cgi?{}", repo.url, sdp.md5);
     let url = url
         .parse::<hyper::Uri>()
-        .map_err(FileDownloadError::InvalidUri)?;
+        .map_err(|e| Error::network(&url, e))?;
 
     download_sdp_files_with_url(rapid_store, opts, url, download_map, sdp_files).await
 }
@@ -44,6 +75,7 @@ struct BufferedReader {
     res: Response<Body>,
     buf: Vec<u8>,
     current: usize,
+    total_read: usize,
     progress_function: Option<Box<dyn FnMut(usize)>>,
 }
 
@@ -61,6 +93,7 @@ impl BufferedReader {
             res,
             buf: Vec::new(),
             current: 0,
+            total_read: 0,
             progress_function: None,
         }
     }
@@ -80,6 +113,7 @@ impl BufferedReader {
                 .map_err(|_e| ReadingError::NetworkError)?;
             let chunk = next;
             self.current += chunk.len();
+            self.total_read += chunk.len();
             self.buf.extend_from_slice(&chunk[..]);
 
             if let Some(progress_function) = &mut self.progress_function {
@@ -94,6 +128,7 @@ impl BufferedReader {
     pub async fn read_remainder(&mut self) -> Result<Vec<u8>, ReadingError> {
         while let Some(next) = self.res.data().await {
             let chunk = next.map_err(|_e| ReadingError::NetworkError)?;
+            self.total_read += chunk.len();
             self.buf.extend_from_slice(&chunk[..]);
         }
 
@@ -112,18 +147,21 @@ pub async fn download_sdp_files_with_url(
     url: Uri,
     download_map: Vec<u8>,
     sdp_files: &[SdpPackage],
-) -> Result<(), FileDownloadError> {
+) -> Result<PoolDownloadReport, Error> {
     assert_ne!(sdp_files.len(), 0);
     assert!(download_map.iter().any(|f| *f != 0));
-    let gzipped = gz::gzip_data(download_map.as_slice())
-        .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+    let url_string = url.to_string();
+    let gzipped =
+        gz::gzip_data(download_map.as_slice()).map_err(|e| Error::network(&url_string, e))?;
 
-    let req = Request::builder()
-        .method("POST")
-        .uri(url)
-        .body(hyper::Body::from(gzipped))
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
-    let ResponseWithSize { res, size } = http_download_with_request(req).await?;
+    let ResponseWithSize { res, size } = http_download_with_request(opts, || {
+        Request::builder()
+            .method("POST")
+            .uri(url.clone())
+            .body(hyper::Body::from(gzipped.clone()))
+            .map_err(|e| Error::network(&url_string, e))
+    })
+    .await?;
 
     const LENGTH_SIZE: usize = 4;
 
@@ -135,35 +173,43 @@ pub async fn download_sdp_files_with_url(
         downloaded_size += downloaded;
         print_function.event(Event::DownloadProgress(downloaded_size));
     }));
-    let missing_files = rapid_store.find_missing_files(sdp_files);
-    for sdp_package in missing_files.iter() {
+    let requested_files = sdp_files
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| download_map[i / 8] & (1 << (i % 8)) != 0)
+        .map(|(_, sdp_package)| sdp_package)
+        .collect::<Vec<_>>();
+    let mut report = PoolDownloadReport {
+        files_requested: requested_files.len(),
+        ..Default::default()
+    };
+    for sdp_package in requested_files {
         let file_size = reader
             .read_amount(LENGTH_SIZE)
             .await
-            .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+            .map_err(|e| Error::network(&url_string, e))?;
         let file_size = u32::from_be_bytes(
-            slice_to_u4(&file_size)
-                .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?,
+            slice_to_u4(&file_size).map_err(|e| Error::network(&url_string, e))?,
         ) as usize;
 
         let file_data = reader
             .read_amount(file_size)
             .await
-            .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+            .map_err(|e| Error::network(&url_string, e))?;
 
         let dest = rapid_store.get_pool_path(sdp_package);
-        std::fs::create_dir_all(
-            dest.parent()
-                .context(format!("No parent directory for path: {dest:?}"))
-                .map_err(FileDownloadError::FilesystemError)?,
-        )
-        .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
-        let mut file =
-            File::create(&dest).map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::filesystem(parent, e))?;
+        }
+        // Never write through a hardlink shared with a cache root
+        if dest.exists() {
+            fs::remove_file(&dest).map_err(|e| Error::filesystem(&dest, e))?;
+        }
+        let mut file = File::create(&dest).map_err(|e| Error::filesystem(&dest, e))?;
         file.write_all(&file_data)
-            .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
-        file.flush()
-            .map_err(|e| FileDownloadError::FilesystemError(e.into()))?;
+            .map_err(|e| Error::filesystem(&dest, e))?;
+        file.flush().map_err(|e| Error::filesystem(&dest, e))?;
+        report.files_written += 1;
         // let mut file = File::create(&dest).await?;
         // file.write(&file_data).await?;
         // file.flush().await?;
@@ -175,12 +221,20 @@ pub async fn download_sdp_files_with_url(
                     opts.print.event(Event::Error(format!(
                         "File ({dest:?}) size on disk ({file_size_on_disk}) different than in memory ({file_size})"
                     )));
+                    report.issues.push((
+                        dest.clone(),
+                        FileIssue::SizeMismatch {
+                            expected: file_size as u64,
+                            on_disk: file_size_on_disk,
+                        },
+                    ));
                 }
             }
             Err(err) => {
                 opts.print.event(Event::Error(format!(
                     "Cannot obtain file ({dest:?}) disk size ({err:?}). Unable to verify correctness ({file_size})"
                 )));
+                report.issues.push((dest.clone(), FileIssue::Unreadable));
             }
         };
 
@@ -193,6 +247,7 @@ pub async fn download_sdp_files_with_url(
             Some(err) => {
                 opts.print
                     .event(Event::Error(format!("Invalid file: {err:?} {pool_path:?}")));
+                report.issues.push((pool_path, FileIssue::Invalid(err)));
             }
         }
     }
@@ -201,13 +256,41 @@ pub async fn download_sdp_files_with_url(
     let remaining = reader
         .read_remainder()
         .await
-        .map_err(|e| FileDownloadError::InvalidServerResponse(e.into()))?;
+        .map_err(|e| Error::network(&url_string, e))?;
     if !remaining.is_empty() {
         opts.print.event(Event::Error(format!(
             "There are {} bytes remaining in the stream, should be empty.",
             remaining.len()
         )));
     }
+    report.trailing_bytes = remaining.len();
+    report.bytes_transferred = reader.total_read;
+
+    Ok(report)
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_failed_counts_each_file_once() {
+        let report = PoolDownloadReport {
+            files_requested: 3,
+            files_written: 3,
+            issues: vec![
+                (
+                    PathBuf::from("a"),
+                    FileIssue::SizeMismatch {
+                        expected: 2,
+                        on_disk: 1,
+                    },
+                ),
+                (PathBuf::from("a"), FileIssue::Invalid(FileError::Corrupt)),
+                (PathBuf::from("b"), FileIssue::Unreadable),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(report.files_failed(), 2);
+    }
 }
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+
+use crate::{
+    error::Error,
+    gc::{self, GcReport},
+    rapid::{parsing, rapid_store::RapidStore, types::Repo},
+};
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub kept_sdps: Vec<String>,
+    pub removed_sdps: Vec<String>,
+    pub gc: GcReport,
+}
+
+/// Keeps the `keep` most recent downloaded sdps of each repo (all repos if `repo` is None), as well as
+/// the `pinned` sdp md5s, deleting the other sdps and any pool files no longer referenced.
+///
+/// Recency is the order in which sdps last appear in the repo's versions.gz, as tag aliases such as
+/// `repo:test` share the md5 of the latest revision but may be listed before it.
+/// Downloaded sdps that no repo lists are left alone.
+pub fn prune(
+    rapid_store: &RapidStore,
+    keep: usize,
+    repo: Option<&str>,
+    pinned: &[String],
+) -> Result<PruneReport, Error> {
+    let repos = parsing::parse_repos_from_file(&rapid_store.find_registry_path())?;
+    let repos: Vec<Repo> = match repo {
+        Some(name) => vec![repos
+            .into_iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| Error::NotFound(format!("repo {name}")))?],
+        None => repos,
+    };
+
+    let mut report = PruneReport::default();
+    for repo in repos.iter() {
+        let repo_path = rapid_store.find_repo_path(repo);
+        if !repo_path.exists() {
+            continue;
+        }
+        let sdps = parsing::read_rapid_from_file(&repo_path)?;
+
+        let mut seen = HashSet::new();
+        let mut installed: Vec<String> = sdps
+            .into_iter()
+            .rev()
+            .map(|sdp| sdp.md5)
+            .filter(|md5| seen.insert(md5.clone()))
+            .filter(|md5| rapid_store.get_sdp_path_from_md5(md5).exists())
+            .collect();
+        installed.reverse();
+
+        let remove_count = installed.len().saturating_sub(keep);
+        for (i, md5) in installed.into_iter().enumerate() {
+            if i < remove_count && !pinned.contains(&md5) {
+                report.removed_sdps.push(md5);
+            } else {
+                report.kept_sdps.push(md5);
+            }
+        }
+    }
+
+    for md5 in report.removed_sdps.iter() {
+        gc::remove_file(&rapid_store.get_sdp_path_from_md5(md5), false)?;
+    }
+    report.gc = gc::collect_garbage(rapid_store, false)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_and_pinned() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5s: Vec<String> = (1..=4)
+            .map(|i| {
+                test_utils::add_local_archive(
+                    root.path(),
+                    "test",
+                    &format!("test:{i}"),
+                    &format!("Test {i}"),
+                    &[("a.lua", b"shared"), ("b.lua", format!("v{i}").as_bytes())],
+                )
+            })
+            .collect();
+        let other = test_utils::add_local_archive(
+            root.path(),
+            "other",
+            "other:1",
+            "Other 1",
+            &[("a.lua", b"other")],
+        );
+
+        let report = prune(&rapid_store, 2, Some("test"), &[md5s[0].clone()]).unwrap();
+        assert_eq!(report.removed_sdps, vec![md5s[1].clone()]);
+        assert_eq!(
+            report.gc.unreferenced_files,
+            vec![test_utils::pool_path(root.path(), b"v2")]
+        );
+        for md5 in [&md5s[0], &md5s[2], &md5s[3], &other] {
+            assert!(rapid_store.get_sdp_path_from_md5(md5).exists());
+        }
+        assert!(test_utils::pool_path(root.path(), b"shared").exists());
+
+        let report = prune(&rapid_store, 1, None, &[]).unwrap();
+        assert_eq!(report.removed_sdps, vec![md5s[0].clone(), md5s[2].clone()]);
+        assert_eq!(report.kept_sdps, vec![md5s[3].clone(), other]);
+
+        assert!(matches!(
+            prune(&rapid_store, 1, Some("missing"), &[]),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn aliases_dont_make_revisions_older() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5s: Vec<String> = (1..=3)
+            .map(|i| {
+                test_utils::add_local_archive(
+                    root.path(),
+                    "test",
+                    &format!("test:{i}"),
+                    &format!("Test {i}"),
+                    &[("a.lua", format!("v{i}").as_bytes())],
+                )
+            })
+            .collect();
+        // The alias of the latest revision is listed first
+        let versions = format!(
+            "test:test,{},,Test 3\ntest:1,{},,Test 1\ntest:2,{},,Test 2\ntest:3,{},,Test 3\n",
+            md5s[2], md5s[0], md5s[1], md5s[2]
+        );
+        std::fs::write(
+            root.path()
+                .join("rapid/repos.springrts.com/test/versions.gz"),
+            crate::gz::gzip_data(versions.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+        let report = prune(&rapid_store, 1, Some("test"), &[]).unwrap();
+        assert_eq!(report.removed_sdps, vec![md5s[0].clone(), md5s[1].clone()]);
+        assert_eq!(report.kept_sdps, vec![md5s[2].clone()]);
+    }
+
+    #[test]
+    fn registry_in_a_data_dir() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store =
+            RapidStore::new(root.path().to_owned()).with_data_dirs([data_dir.path().to_owned()]);
+        let md5s: Vec<String> = (1..=2)
+            .map(|i| {
+                test_utils::add_local_archive(
+                    data_dir.path(),
+                    "test",
+                    &format!("test:{i}"),
+                    &format!("Test {i}"),
+                    &[("a.lua", format!("v{i}").as_bytes())],
+                )
+            })
+            .collect();
+        // Only the sdp of the first revision was downloaded into the root
+        std::fs::create_dir_all(rapid_store.get_packages_dir()).unwrap();
+        std::fs::rename(
+            data_dir.path().join(format!("packages/{}.sdp", md5s[0])),
+            rapid_store.get_sdp_path_from_md5(&md5s[0]),
+        )
+        .unwrap();
+
+        let report = prune(&rapid_store, 0, None, &[]).unwrap();
+        assert_eq!(report.removed_sdps, vec![md5s[0].clone()]);
+        assert!(!rapid_store.find_sdp_path_from_md5(&md5s[0]).exists());
+        assert!(rapid_store.find_sdp_path_from_md5(&md5s[1]).exists());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use md5::{Digest, Md5};
+
+use crate::{error::Error, rapid::rapid_store::RapidStore};
+
+const MANIFEST_FILE: &str = "manifest.txt";
+
+/// A corrupt file moved out of the pool, kept for investigation.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QuarantineEntry {
+    pub original_path: PathBuf,
+    pub quarantined_path: PathBuf,
+    pub expected_md5: String,
+    /// None if the file couldn't be decompressed
+    pub actual_md5: Option<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+pub fn get_quarantine_dir(rapid_store: &RapidStore) -> PathBuf {
+    rapid_store.root.join("quarantine")
+}
+
+/// Moves `path` into the quarantine folder and records it in the manifest.
+pub fn quarantine_file(
+    rapid_store: &RapidStore,
+    path: &Path,
+    expected_md5: &str,
+) -> Result<QuarantineEntry, Error> {
+    let quarantine_dir = get_quarantine_dir(rapid_store);
+    fs::create_dir_all(&quarantine_dir).map_err(|e| Error::filesystem(&quarantine_dir, e))?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let entry = QuarantineEntry {
+        original_path: path.to_owned(),
+        quarantined_path: quarantine_dir.join(format!("{}-{file_name}", now.as_nanos())),
+        expected_md5: expected_md5.to_owned(),
+        actual_md5: crate::gz::read_binary_gz_from_file(path)
+            .ok()
+            .map(|data| format!("{:x}", Md5::digest(data))),
+        timestamp: now.as_secs(),
+    };
+    fs::rename(&entry.original_path, &entry.quarantined_path)
+        .map_err(|e| Error::filesystem(path, e))?;
+
+    let manifest_path = quarantine_dir.join(MANIFEST_FILE);
+    let mut manifest = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&manifest_path)
+        .map_err(|e| Error::filesystem(&manifest_path, e))?;
+    writeln!(
+        manifest,
+        "{}\t{}\t{}\t{}\t{}",
+        entry.timestamp,
+        entry.expected_md5,
+        entry.actual_md5.as_deref().unwrap_or("-"),
+        entry.quarantined_path.display(),
+        entry.original_path.display()
+    )
+    .map_err(|e| Error::filesystem(&manifest_path, e))?;
+
+    Ok(entry)
+}
+
+/// Quarantined files, oldest first.
+pub fn list(rapid_store: &RapidStore) -> Result<Vec<QuarantineEntry>, Error> {
+    let manifest = get_quarantine_dir(rapid_store).join(MANIFEST_FILE);
+    if !manifest.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(fs::read_to_string(&manifest)
+        .map_err(|e| Error::filesystem(&manifest, e))?
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 5 {
+                return None;
+            }
+            Some(QuarantineEntry {
+                timestamp: fields[0].parse().ok()?,
+                expected_md5: fields[1].to_owned(),
+                actual_md5: (fields[2] != "-").then(|| fields[2].to_owned()),
+                quarantined_path: PathBuf::from(fields[3]),
+                original_path: PathBuf::from(fields[4]),
+            })
+        })
+        .collect())
+}
+
+/// Deletes all quarantined files and the manifest. Returns the number of files deleted.
+pub fn purge(rapid_store: &RapidStore) -> Result<usize, Error> {
+    let quarantine_dir = get_quarantine_dir(rapid_store);
+    if !quarantine_dir.exists() {
+        return Ok(0);
+    }
+
+    let count = list(rapid_store)?.len();
+    fs::remove_dir_all(&quarantine_dir).map_err(|e| Error::filesystem(&quarantine_dir, e))?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_list_purge() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a"), ("b", b"b")],
+        );
+        let wrong_hash = test_utils::pool_path(root.path(), b"a");
+        let corrupt = test_utils::pool_path(root.path(), b"b");
+        fs::write(&corrupt, b"not gzipped").unwrap();
+
+        let first = quarantine_file(&rapid_store, &wrong_hash, "expected").unwrap();
+        let second = quarantine_file(&rapid_store, &corrupt, "expected").unwrap();
+        assert!(!wrong_hash.exists());
+        assert!(first.quarantined_path.exists());
+        assert_eq!(
+            first.actual_md5.as_deref(),
+            Some("0cc175b9c0f1b6a831c399e269772661")
+        );
+        assert_eq!(second.actual_md5, None);
+
+        assert_eq!(list(&rapid_store).unwrap(), vec![first, second]);
+        assert_eq!(purge(&rapid_store).unwrap(), 2);
+        assert!(list(&rapid_store).unwrap().is_empty());
+    }
+}
@@ -1,10 +1,9 @@
 use std::path;
 use std::str;
-use std::u32;
 
 use thiserror::Error;
 
-use crate::gz::GzReadError;
+use crate::error::Error;
 
 use super::types::{Repo, Sdp, SdpPackage};
 
@@ -15,8 +14,8 @@ pub struct CorruptSdpPackage {
     source: anyhow::Error,
 }
 
-pub fn parse_repos_from_file(path: &path::Path) -> Result<Vec<Repo>, GzReadError> {
-    let s = crate::gz::read_gz_from_file(path)?;
+pub fn parse_repos_from_file(path: &path::Path) -> Result<Vec<Repo>, Error> {
+    let s = crate::gz::read_gz_from_file(path).map_err(|e| Error::metadata(path, e))?;
     Ok(parse_repos_from_str(&s))
 }
 
@@ -37,8 +36,8 @@ pub fn parse_repos_from_str(s: &str) -> Vec<Repo> {
     entries
 }
 
-pub fn read_rapid_from_file(path: &path::Path) -> Result<Vec<Sdp>, GzReadError> {
-    let parsed_gz = crate::gz::read_gz_from_file(path)?;
+pub fn read_rapid_from_file(path: &path::Path) -> Result<Vec<Sdp>, Error> {
+    let parsed_gz = crate::gz::read_gz_from_file(path).map_err(|e| Error::metadata(path, e))?;
     Ok(read_rapid_from_str(&parsed_gz))
 }
 
@@ -64,13 +63,10 @@ pub fn read_rapid_from_str(parsed_gz: &str) -> Vec<Sdp> {
     entries
 }
 
-pub fn load_sdp_packages_from_file(
-    dest: &path::Path,
-) -> Result<Vec<SdpPackage>, CorruptSdpPackage> {
-    let data = crate::gz::read_binary_gz_from_file(dest)
-        .map_err(|e| CorruptSdpPackage { source: e.into() })?;
+pub fn load_sdp_packages_from_file(dest: &path::Path) -> Result<Vec<SdpPackage>, Error> {
+    let data = crate::gz::read_binary_gz_from_file(dest).map_err(|e| Error::metadata(dest, e))?;
 
-    load_sdp_packages(&data)
+    load_sdp_packages(&data).map_err(|e| Error::metadata(dest, e))
 }
 
 pub fn load_sdp_packages(data: &[u8]) -> Result<Vec<SdpPackage>, CorruptSdpPackage> {
@@ -111,3 +107,62 @@ pub fn load_sdp_packages(data: &[u8]) -> Result<Vec<SdpPackage>, CorruptSdpPacka
 
     Ok(sdp_files)
 }
+
+/// Inverse of [`load_sdp_packages`].
+pub fn sdp_packages_to_bytes(packages: &[SdpPackage]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for package in packages {
+        data.push(package.name.len() as u8);
+        data.extend_from_slice(package.name.as_bytes());
+        data.extend_from_slice(&package.md5_bin);
+        data.extend_from_slice(&package.crc32);
+        data.extend_from_slice(&package.size.to_le_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn sdp_packages_roundtrip() {
+        let mut data = vec![5];
+        data.extend_from_slice(b"a.lua");
+        data.extend_from_slice(&[0xab; 16]);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data.extend_from_slice(&42u32.to_le_bytes());
+
+        let packages = load_sdp_packages(&data).unwrap();
+        assert_eq!(packages[0].name, "a.lua");
+        assert_eq!(&packages[0].md5, "ab".repeat(16).as_bytes());
+        assert_eq!(packages[0].size, 42);
+        assert_eq!(sdp_packages_to_bytes(&packages), data);
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let root = tempfile::tempdir().unwrap();
+        let missing = root.path().join("missing.gz");
+        assert!(matches!(
+            parse_repos_from_file(&missing),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            read_rapid_from_file(&missing),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            load_sdp_packages_from_file(&missing),
+            Err(Error::NotFound(_))
+        ));
+
+        fs::write(&missing, b"not gzip").unwrap();
+        assert!(matches!(
+            parse_repos_from_file(&missing),
+            Err(Error::Metadata { .. })
+        ));
+    }
+}
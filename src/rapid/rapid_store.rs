@@ -1,27 +1,59 @@
-use std::path::{self, PathBuf};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{self, Path, PathBuf};
 
-use crate::gz::GzReadError;
+use crate::error::Error;
 
 use super::super::util;
 use super::parsing::read_rapid_from_file;
 use super::types::{Repo, Sdp, SdpPackage};
 
-#[derive(Debug)]
+/// A rapid root that downloads are written to, plus read-only data dirs (e.g. a system-wide pool)
+/// whose content is used instead of downloading it again.
+///
+/// `get_*` paths are in the root and meant for writing; `find_*` paths are wherever the file
+/// exists, searching the root first and then the data dirs in order.
+#[derive(Clone, Debug)]
 pub struct RapidStore {
     pub root: PathBuf,
+    pub data_dirs: Vec<PathBuf>,
 }
 
 impl Default for RapidStore {
     fn default() -> Self {
-        RapidStore {
-            root: util::default_spring_dir(),
-        }
+        RapidStore::new(util::default_spring_dir())
     }
 }
 
 impl RapidStore {
     pub fn new(path: PathBuf) -> Self {
-        Self { root: path }
+        Self {
+            root: path,
+            data_dirs: Vec::new(),
+        }
+    }
+
+    /// Adds read-only data dirs, searched in order after the root. The root itself is skipped.
+    pub fn with_data_dirs(mut self, data_dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        for dir in data_dirs {
+            if dir != self.root && !self.data_dirs.contains(&dir) {
+                self.data_dirs.push(dir);
+            }
+        }
+        self
+    }
+
+    /// The root followed by the data dirs.
+    pub fn all_dirs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.root.as_path()).chain(self.data_dirs.iter().map(PathBuf::as_path))
+    }
+
+    /// The first existing `relative` path, or the one in the root if there's none.
+    fn find(&self, relative: &Path) -> PathBuf {
+        self.all_dirs()
+            .map(|dir| dir.join(relative))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| self.root.join(relative))
     }
 
     // pub fn find_repo(&self, name: &str) -> Result<Option<Repo>, Box<dyn Error>> {
@@ -29,11 +61,11 @@ impl RapidStore {
     //     Ok(repos.into_iter().find(|repo| repo.name.contains(name)))
     // }
 
-    pub fn find_sdp(&self, repo: &Repo, name: &str) -> Result<Option<Sdp>, GzReadError> {
-        let repo_path = self.root.join(&format!(
+    pub fn find_sdp(&self, repo: &Repo, name: &str) -> Result<Option<Sdp>, Error> {
+        let repo_path = self.find(Path::new(&format!(
             "rapid/repos.springrts.com/{}/versions.gz",
             repo.name
-        ));
+        )));
         let sdps = read_rapid_from_file(&repo_path)?;
         Ok(sdps
             .into_iter()
@@ -43,21 +75,24 @@ impl RapidStore {
     pub fn find_missing_files<'a>(&self, sdp_files: &'a [SdpPackage]) -> Vec<&'a SdpPackage> {
         sdp_files
             .iter()
-            .filter(|sdp_file| !self.get_pool_path(sdp_file).exists())
+            .filter(|sdp_file| !self.find_pool_path(sdp_file).exists())
             .collect::<Vec<&SdpPackage>>()
     }
 
     pub fn get_registry_path(&self) -> path::PathBuf {
-        self.root.join("rapid/repos.springrts.com/repos.gz")
+        self.root.join(REGISTRY_PATH)
+    }
+
+    pub fn find_registry_path(&self) -> path::PathBuf {
+        self.find(Path::new(REGISTRY_PATH))
     }
 
     pub fn get_repo_path(&self, repo: &Repo) -> path::PathBuf {
-        let mut http_split: Vec<&str> = repo.url.split("http://").collect();
-        if http_split.len() != 2 {
-            http_split = repo.url.split("https://").collect();
-        }
-        let name = http_split[1];
-        self.root.join(format!("rapid/{name}/versions.gz"))
+        self.root.join(repo_relative_path(repo))
+    }
+
+    pub fn find_repo_path(&self, repo: &Repo) -> path::PathBuf {
+        self.find(&repo_relative_path(repo))
     }
 
     pub fn get_sdp_path(&self, sdp: &Sdp) -> path::PathBuf {
@@ -65,19 +100,52 @@ impl RapidStore {
     }
 
     pub fn get_sdp_path_from_md5(&self, sdp_md5: &str) -> path::PathBuf {
-        self.root
-            .join(path::PathBuf::from(format!("packages/{sdp_md5}.sdp")))
+        self.root.join(sdp_relative_path(sdp_md5))
+    }
+
+    pub fn find_sdp_path(&self, sdp: &Sdp) -> path::PathBuf {
+        self.find_sdp_path_from_md5(&sdp.md5)
+    }
+
+    pub fn find_sdp_path_from_md5(&self, sdp_md5: &str) -> path::PathBuf {
+        self.find(&sdp_relative_path(sdp_md5))
+    }
+
+    pub fn get_packages_dir(&self) -> path::PathBuf {
+        self.root.join("packages")
+    }
+
+    pub fn get_pool_dir(&self) -> path::PathBuf {
+        self.root.join("pool")
+    }
+
+    /// Paths of all sdp files in the packages folder of the root. Missing folder means nothing is installed.
+    pub fn list_sdp_paths(&self) -> Result<Vec<path::PathBuf>, Error> {
+        list_sdp_paths_in(&self.get_packages_dir())
+    }
+
+    /// Like [`Self::list_sdp_paths`], but over all dirs. An sdp in several dirs is listed once,
+    /// from the first one.
+    pub fn list_all_sdp_paths(&self) -> Result<Vec<path::PathBuf>, Error> {
+        let mut seen = HashSet::new();
+        let mut sdp_paths = Vec::new();
+        for dir in self.all_dirs() {
+            for path in list_sdp_paths_in(&dir.join("packages"))? {
+                if seen.insert(path.file_name().map(|name| name.to_owned())) {
+                    sdp_paths.push(path);
+                }
+            }
+        }
+
+        Ok(sdp_paths)
     }
 
     pub fn get_pool_path(&self, sdp_package: &SdpPackage) -> path::PathBuf {
-        let file_path = self.root.join(format!(
-            "pool/{}{}/{}.gz",
-            sdp_package.md5[0] as char,
-            sdp_package.md5[1] as char,
-            std::str::from_utf8(&sdp_package.md5[2..32]).unwrap()
-        ));
+        self.root.join(pool_relative_path(sdp_package))
+    }
 
-        file_path
+    pub fn find_pool_path(&self, sdp_package: &SdpPackage) -> path::PathBuf {
+        self.find(&pool_relative_path(sdp_package))
     }
 
     pub fn get_missing_files_indices(&self, sdp_files: &[SdpPackage]) -> Vec<u8> {
@@ -85,7 +153,7 @@ impl RapidStore {
         let mut download_map: Vec<u8> = vec![0; map_length];
 
         for (i, sdp_file) in sdp_files.iter().enumerate() {
-            let file_path = self.get_pool_path(sdp_file);
+            let file_path = self.find_pool_path(sdp_file);
 
             if !file_path.exists() {
                 download_map[i / 8] |= 1 << (i % 8);
@@ -96,6 +164,50 @@ impl RapidStore {
     }
 }
 
+const REGISTRY_PATH: &str = "rapid/repos.springrts.com/repos.gz";
+
+fn repo_relative_path(repo: &Repo) -> PathBuf {
+    let mut http_split: Vec<&str> = repo.url.split("http://").collect();
+    if http_split.len() != 2 {
+        http_split = repo.url.split("https://").collect();
+    }
+    let name = http_split[1];
+    PathBuf::from(format!("rapid/{name}/versions.gz"))
+}
+
+fn sdp_relative_path(sdp_md5: &str) -> PathBuf {
+    PathBuf::from(format!("packages/{sdp_md5}.sdp"))
+}
+
+fn pool_relative_path(sdp_package: &SdpPackage) -> PathBuf {
+    PathBuf::from(format!(
+        "pool/{}{}/{}.gz",
+        sdp_package.md5[0] as char,
+        sdp_package.md5[1] as char,
+        std::str::from_utf8(&sdp_package.md5[2..32]).unwrap()
+    ))
+}
+
+fn list_sdp_paths_in(packages_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !packages_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sdp_paths = Vec::new();
+    let entries = fs::read_dir(packages_dir).map_err(|e| Error::filesystem(packages_dir, e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::filesystem(packages_dir, e))?
+            .path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "sdp") {
+            sdp_paths.push(path);
+        }
+    }
+    sdp_paths.sort();
+
+    Ok(sdp_paths)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,4 +247,47 @@ mod tests {
         assert_eq!(sdp.md5, "d80d786597510d1358be3b04a7e9146e");
         assert_eq!(sdp.archive_name, "SpringBoard Core 0.5.2");
     }
+
+    #[test]
+    fn data_dirs_are_searched_after_root() {
+        let root = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned())
+            .with_data_dirs([root.path().to_owned(), shared.path().to_owned()]);
+        assert_eq!(rapid_store.data_dirs, vec![shared.path().to_owned()]);
+
+        let md5 = test_utils::add_local_archive(
+            shared.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a"), ("b", b"b")],
+        );
+        let sdp_packages = crate::rapid::parsing::load_sdp_packages_from_file(
+            &rapid_store.find_sdp_path_from_md5(&md5),
+        )
+        .unwrap();
+        assert!(rapid_store.find_missing_files(&sdp_packages).is_empty());
+        assert_eq!(
+            rapid_store.get_missing_files_indices(&sdp_packages),
+            vec![0]
+        );
+        assert!(rapid_store
+            .find_pool_path(&sdp_packages[0])
+            .starts_with(shared.path()));
+        assert!(rapid_store
+            .get_pool_path(&sdp_packages[0])
+            .starts_with(root.path()));
+        assert!(rapid_store.list_sdp_paths().unwrap().is_empty());
+        assert_eq!(rapid_store.list_all_sdp_paths().unwrap().len(), 1);
+
+        // A copy in the root takes precedence
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        assert!(rapid_store
+            .find_pool_path(&sdp_packages[0])
+            .starts_with(root.path()));
+        assert!(rapid_store
+            .find_sdp_path_from_md5(&md5)
+            .starts_with(shared.path()));
+    }
 }
@@ -1,10 +1,10 @@
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Repo {
     pub name: String,
     pub url: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Sdp {
     pub rapid_name: String,
     pub md5: String,
@@ -12,7 +12,7 @@ pub struct Sdp {
     pub archive_name: String,
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct SdpPackage {
     pub name: String,
     pub md5: [u8; 32],
@@ -1,28 +1,232 @@
-use anyhow::Context;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use super::{
-    api::DownloadOptions, metadata, pool_downloader, rapid::rapid_store::RapidStore,
-    validation::check_if_sdp_needs_download,
+    api::DownloadOptions,
+    error::Error,
+    event::Event,
+    lock::{self, LockMode},
+    metadata, pool_cache,
+    pool_downloader::{self, FileIssue},
+    rapid::{
+        rapid_store::RapidStore,
+        types::{Repo, Sdp, SdpPackage},
+    },
+    validation::validate_sdp_packages,
 };
 
+#[derive(Debug)]
+pub struct DownloadReport {
+    pub repo: Repo,
+    pub sdp: Sdp,
+    /// Pool files requested from the streamer, 0 if everything was already downloaded
+    pub files_requested: usize,
+    /// Pool files taken from the cache root instead of being downloaded
+    pub files_linked: usize,
+    pub files_written: usize,
+    pub files_failed: usize,
+    /// Compressed bytes received from the streamer
+    pub bytes_transferred: usize,
+    /// Bytes the streamer sent after the last requested file, a malformed response unless 0
+    pub trailing_bytes: usize,
+    pub duration: Duration,
+    pub issues: Vec<(PathBuf, FileIssue)>,
+}
+
+impl DownloadReport {
+    pub fn is_success(&self) -> bool {
+        self.files_failed == 0
+            && self.files_written == self.files_requested
+            && self.trailing_bytes == 0
+    }
+}
+
 pub async fn download(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
     fullname: &str,
-) -> anyhow::Result<()> {
+) -> Result<DownloadReport, Error> {
+    let started = Instant::now();
     let (repo, sdp) = metadata::query_metadata(rapid_store, opts, fullname)
         .await?
-        .context("No such item")?;
+        .ok_or_else(|| Error::NotFound(fullname.to_owned()))?;
 
     let sdp_files = metadata::query_sdp_files(rapid_store, opts, &repo, &sdp).await?;
 
-    if !check_if_sdp_needs_download(rapid_store, &sdp.md5) {
-        return Ok(());
+    let mut report = DownloadReport {
+        repo,
+        sdp,
+        files_requested: 0,
+        files_linked: 0,
+        files_written: 0,
+        files_failed: 0,
+        bytes_transferred: 0,
+        trailing_bytes: 0,
+        duration: Duration::ZERO,
+        issues: Vec::new(),
+    };
+    let mut download_map = files_to_download(rapid_store, opts, &sdp_files).await;
+    if download_map.iter().any(|f| *f != 0) {
+        // Shared, as other roots read and fill the cache concurrently, but it keeps gc out
+        let cache = match &opts.cache_root {
+            Some(cache_root) => {
+                let cache = RapidStore::new(cache_root.clone());
+                match lock::lock(&cache, opts, LockMode::Shared).await {
+                    Ok(cache_lock) => Some((cache, cache_lock)),
+                    Err(err) => {
+                        opts.print
+                            .event(Event::Error(format!("Not using the cache root: {err}")));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        if let Some((cache, _)) = &cache {
+            report.files_linked =
+                pool_cache::take_from_cache(rapid_store, cache, &sdp_files, &mut download_map);
+        }
+
+        if download_map.iter().any(|f| *f != 0) {
+            let pool_report = pool_downloader::download_sdp_files(
+                rapid_store,
+                opts,
+                &report.repo,
+                &report.sdp,
+                download_map.clone(),
+                &sdp_files,
+            )
+            .await?;
+
+            report.files_requested = pool_report.files_requested;
+            report.files_written = pool_report.files_written;
+            report.files_failed = pool_report.files_failed();
+            report.bytes_transferred = pool_report.bytes_transferred;
+            report.trailing_bytes = pool_report.trailing_bytes;
+            report.issues = pool_report.issues;
+        }
+
+        if let Some((cache, _)) = &cache {
+            let downloaded = sdp_files.iter().enumerate().filter(|(i, sdp_file)| {
+                download_map[i / 8] & (1 << (i % 8)) != 0
+                    && !report
+                        .issues
+                        .iter()
+                        .any(|(path, _)| *path == rapid_store.get_pool_path(sdp_file))
+            });
+            // The download itself succeeded, so a cache that can't be filled isn't fatal
+            if let Err(err) = pool_cache::fill_cache(
+                rapid_store,
+                cache,
+                &report.sdp.md5,
+                downloaded.map(|(_, f)| f),
+            ) {
+                opts.print.event(Event::Error(format!(
+                    "Failed to fill the cache root: {err}"
+                )));
+            }
+        }
+    }
+    report.duration = started.elapsed();
+
+    Ok(report)
+}
+
+/// Download map of the pool files that are missing or fail validation, so corrupt files are
+/// replaced rather than kept.
+async fn files_to_download(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    sdp_files: &[SdpPackage],
+) -> Vec<u8> {
+    let invalid_paths: HashSet<PathBuf> = validate_sdp_packages(rapid_store, opts, sdp_files)
+        .await
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+
+    let mut download_map = vec![0; sdp_files.len() / 8 + 1];
+    for (i, sdp_file) in sdp_files.iter().enumerate() {
+        if invalid_paths.contains(&rapid_store.find_pool_path(sdp_file)) {
+            download_map[i / 8] |= 1 << (i % 8);
+        }
     }
 
-    let download_map = rapid_store.get_missing_files_indices(&sdp_files);
-    pool_downloader::download_sdp_files(rapid_store, opts, &repo, &sdp, download_map, &sdp_files)
-        .await?;
+    download_map
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
 
-    Ok(())
+    use super::*;
+    use crate::api::MetadataSource;
+
+    #[tokio::test]
+    async fn offline_download_without_sdp_is_not_found() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 =
+            test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+        fs::remove_file(rapid_store.get_sdp_path_from_md5(&md5)).unwrap();
+        let opts = DownloadOptions {
+            offline: true,
+            ..DownloadOptions::new(MetadataSource::Local)
+        };
+
+        let err = download(&rapid_store, &opts, "test:1").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)), "{err:?}");
+    }
+
+    #[test]
+    fn trailing_bytes_are_a_failure() {
+        let mut report = DownloadReport {
+            repo: Repo {
+                name: "test".to_owned(),
+                url: "https://repos.springrts.com/test".to_owned(),
+            },
+            sdp: Sdp {
+                rapid_name: "test:1".to_owned(),
+                md5: "d80d786597510d1358be3b04a7e9146e".to_owned(),
+                depends: String::new(),
+                archive_name: "Test 1".to_owned(),
+            },
+            files_requested: 2,
+            files_linked: 0,
+            files_written: 2,
+            files_failed: 0,
+            bytes_transferred: 100,
+            trailing_bytes: 0,
+            duration: Duration::ZERO,
+            issues: Vec::new(),
+        };
+        assert!(report.is_success());
+
+        report.trailing_bytes = 3;
+        assert!(!report.is_success());
+    }
+
+    #[tokio::test]
+    async fn corrupt_files_are_downloaded_again() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"valid"), ("b", b"corrupt"), ("c", b"missing")],
+        );
+        fs::write(test_utils::pool_path(root.path(), b"corrupt"), b"not gzip").unwrap();
+        fs::remove_file(test_utils::pool_path(root.path(), b"missing")).unwrap();
+        let sdp_files = crate::rapid::parsing::load_sdp_packages_from_file(
+            &rapid_store.get_sdp_path_from_md5(&md5),
+        )
+        .unwrap();
+
+        let download_map =
+            files_to_download(&rapid_store, &DownloadOptions::default(), &sdp_files).await;
+        assert_eq!(download_map, vec![0b110]);
+    }
 }
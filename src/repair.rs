@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::{
+    api::DownloadOptions,
+    error::Error,
+    file_download, metadata, pool_downloader, quarantine,
+    rapid::{
+        parsing,
+        rapid_store::RapidStore,
+        types::{Repo, Sdp, SdpPackage},
+    },
+    validation::{self, FileError},
+};
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// The sdp was missing or corrupt and had to be downloaded again
+    pub sdp_redownloaded: bool,
+    pub fixed_files: Vec<PathBuf>,
+    /// Corrupt files moved to the quarantine folder before downloading them again
+    pub quarantined_files: Vec<PathBuf>,
+    /// Files that are still invalid after being downloaded again
+    pub failed_files: Vec<(PathBuf, FileError)>,
+}
+
+impl RepairReport {
+    pub fn is_success(&self) -> bool {
+        self.failed_files.is_empty()
+    }
+}
+
+/// Verifies the item and downloads again exactly the sdp and pool files that are missing or invalid.
+pub async fn repair(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> Result<RepairReport, Error> {
+    let (repo, sdp) = metadata::query_metadata(rapid_store, opts, fullname)
+        .await?
+        .ok_or_else(|| Error::NotFound(fullname.to_owned()))?;
+
+    repair_sdp(rapid_store, opts, &repo, &sdp, None).await
+}
+
+/// Like [`repair`], for the downloaded sdp with this md5 rather than whatever a name resolves to
+/// now. With `invalid_files` from a previous validation (e.g. [`validation::validate_all`]), only
+/// those files are validated again.
+pub async fn repair_by_sdp_md5(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    sdp_md5: &str,
+    invalid_files: Option<Vec<(PathBuf, FileError)>>,
+) -> Result<RepairReport, Error> {
+    let (repo, sdp) = metadata::query_metadata_by_sdp_md5(rapid_store, sdp_md5)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("sdp {sdp_md5}")))?;
+
+    repair_sdp(rapid_store, opts, &repo, &sdp, invalid_files).await
+}
+
+async fn repair_sdp(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    repo: &Repo,
+    sdp: &Sdp,
+    invalid_files: Option<Vec<(PathBuf, FileError)>>,
+) -> Result<RepairReport, Error> {
+    let mut report = RepairReport::default();
+    let sdp_files = load_or_download_sdp(rapid_store, opts, repo, sdp, &mut report).await?;
+
+    // Only files found invalid before are checked again, a repair of another sdp sharing them
+    // may have fixed them since
+    let packages_to_check: Vec<SdpPackage> = match invalid_files {
+        Some(invalid_files) if !report.sdp_redownloaded => {
+            let invalid_paths: HashSet<PathBuf> =
+                invalid_files.into_iter().map(|(path, _)| path).collect();
+            sdp_files
+                .iter()
+                .filter(|package| invalid_paths.contains(&rapid_store.find_pool_path(package)))
+                .cloned()
+                .collect()
+        }
+        _ => sdp_files.clone(),
+    };
+    let invalid_files =
+        validation::validate_sdp_packages(rapid_store, opts, &packages_to_check).await;
+    if invalid_files.is_empty() {
+        return Ok(report);
+    }
+
+    if opts.quarantine {
+        quarantine_corrupt_files(rapid_store, &sdp_files, &invalid_files, &mut report)?;
+    }
+
+    let invalid_paths: HashSet<PathBuf> = invalid_files.into_iter().map(|(path, _)| path).collect();
+    // Invalid files of the data dirs are downloaded to the root, so match by package from here on
+    let invalid_packages: Vec<SdpPackage> = sdp_files
+        .iter()
+        .filter(|package| invalid_paths.contains(&rapid_store.find_pool_path(package)))
+        .cloned()
+        .collect();
+    if invalid_packages.is_empty() {
+        return Ok(report);
+    }
+    pool_downloader::download_sdp_files(
+        rapid_store,
+        opts,
+        repo,
+        sdp,
+        download_map(rapid_store, &sdp_files, &invalid_paths),
+        &sdp_files,
+    )
+    .await?;
+
+    report.failed_files =
+        validation::validate_sdp_packages(rapid_store, opts, &invalid_packages).await;
+    let mut fixed_files: Vec<PathBuf> = invalid_packages
+        .iter()
+        .map(|package| rapid_store.find_pool_path(package))
+        .filter(|path| !report.failed_files.iter().any(|(failed, _)| failed == path))
+        .collect();
+    fixed_files.sort();
+    report.fixed_files = fixed_files;
+
+    Ok(report)
+}
+
+async fn load_or_download_sdp(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    repo: &Repo,
+    sdp: &Sdp,
+    report: &mut RepairReport,
+) -> Result<Vec<SdpPackage>, Error> {
+    if let Ok(sdp_files) = parsing::load_sdp_packages_from_file(&rapid_store.find_sdp_path(sdp)) {
+        return Ok(sdp_files);
+    }
+
+    file_download::download_sdp(rapid_store, opts, repo, sdp).await?;
+    report.sdp_redownloaded = true;
+
+    parsing::load_sdp_packages_from_file(&rapid_store.get_sdp_path(sdp))
+}
+
+fn quarantine_corrupt_files(
+    rapid_store: &RapidStore,
+    sdp_files: &[SdpPackage],
+    invalid_files: &[(PathBuf, FileError)],
+    report: &mut RepairReport,
+) -> Result<(), Error> {
+    // Packages can share a pool file, which is then listed once per package
+    let mut seen = HashSet::new();
+    for (path, file_error) in invalid_files.iter() {
+        // Files of the data dirs are read-only, the root copy just takes precedence
+        if *file_error == FileError::Missing
+            || !path.starts_with(&rapid_store.root)
+            || !seen.insert(path)
+        {
+            continue;
+        }
+        let Some(package) = sdp_files
+            .iter()
+            .find(|package| rapid_store.get_pool_path(package) == *path)
+        else {
+            continue;
+        };
+        quarantine::quarantine_file(
+            rapid_store,
+            path,
+            std::str::from_utf8(&package.md5).unwrap_or_default(),
+        )?;
+        report.quarantined_files.push(path.clone());
+    }
+
+    Ok(())
+}
+
+/// Streamer bitmap requesting the packages whose pool files are in `paths`.
+fn download_map(
+    rapid_store: &RapidStore,
+    sdp_files: &[SdpPackage],
+    paths: &HashSet<PathBuf>,
+) -> Vec<u8> {
+    let mut download_map: Vec<u8> = vec![0; sdp_files.len() / 8 + 1];
+    for (i, sdp_file) in sdp_files.iter().enumerate() {
+        if paths.contains(&rapid_store.find_pool_path(sdp_file)) {
+            download_map[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    download_map
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::MetadataSource;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn nothing_to_repair() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &[("a", b"a")]);
+
+        let report = repair(
+            &rapid_store,
+            &DownloadOptions::new(MetadataSource::Local),
+            "test:1",
+        )
+        .await
+        .unwrap();
+        assert!(report.is_success());
+        assert!(!report.sdp_redownloaded);
+        assert!(report.fixed_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn repair_by_md5_ignores_moving_tags() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::new(MetadataSource::Local);
+        let first = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:test",
+            "Test 1",
+            &[("a", b"a")],
+        );
+        // The tag moves on to another revision
+        test_utils::add_local_archive(root.path(), "test", "test:test", "Test 2", &[("b", b"b")]);
+        let missing = test_utils::pool_path(root.path(), b"a");
+        std::fs::remove_file(&missing).unwrap();
+
+        // Only the known invalid files are downloaded; the streamer is unreachable offline
+        let err = repair_by_sdp_md5(
+            &rapid_store,
+            &DownloadOptions {
+                offline: true,
+                ..DownloadOptions::new(MetadataSource::Local)
+            },
+            &first,
+            Some(vec![(missing.clone(), FileError::Missing)]),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Network { .. }), "{err:?}");
+
+        // Files not listed as invalid aren't hashed again
+        let report = repair_by_sdp_md5(&rapid_store, &opts, &first, Some(Vec::new()))
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert!(report.fixed_files.is_empty());
+    }
+
+    #[test]
+    fn shared_files_are_quarantined_once() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let package = SdpPackage {
+            md5: [b'0'; 32],
+            ..Default::default()
+        };
+        let path = rapid_store.get_pool_path(&package);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"corrupt").unwrap();
+
+        let mut report = RepairReport::default();
+        quarantine_corrupt_files(
+            &rapid_store,
+            &[package.clone(), package],
+            &[
+                (path.clone(), FileError::Corrupt),
+                (path.clone(), FileError::Corrupt),
+            ],
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(report.quarantined_files, vec![path.clone()]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn download_map_selects_invalid_files() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let sdp_files: Vec<SdpPackage> = (0..10u8)
+            .map(|i| SdpPackage {
+                md5: [b'0' + i; 32],
+                ..Default::default()
+            })
+            .collect();
+        let paths = HashSet::from([
+            rapid_store.get_pool_path(&sdp_files[1]),
+            rapid_store.get_pool_path(&sdp_files[9]),
+        ]);
+
+        assert_eq!(
+            download_map(&rapid_store, &sdp_files, &paths),
+            vec![0b0000_0010, 0b0000_0010]
+        );
+    }
+}
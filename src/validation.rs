@@ -1,72 +1,252 @@
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 use md5::{Digest, Md5};
 
 use crate::{
     api::DownloadOptions,
-    metadata::{self, MetadataQueryError},
+    error::Error,
+    event::Event,
+    metadata,
     rapid::{rapid_store::RapidStore, types::SdpPackage},
+    verification_cache::{FileStamp, VerificationCache},
 };
 
 use super::rapid::{parsing, rapid_store};
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FileError {
     Missing,
     Corrupt,
     WrongHash,
 }
 
-pub enum ValidityErrors {
-    MetadataQueryError(MetadataQueryError),
-    MissingSdp,
-    InvalidFiles { files: Vec<(PathBuf, FileError)> },
+/// How many of an sdp's pool files are present on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub present_files: usize,
+    pub missing_files: usize,
+}
+
+impl Completion {
+    pub fn total_files(&self) -> usize {
+        self.present_files + self.missing_files
+    }
+
+    pub fn percentage(&self) -> f64 {
+        if self.total_files() == 0 {
+            return 100.0;
+        }
+        100.0 * self.present_files as f64 / self.total_files() as f64
+    }
 }
 
-pub fn check_if_sdp_needs_download(rapid_store: &rapid_store::RapidStore, md5: &str) -> bool {
-    validate_by_sdp_md5(rapid_store, md5).is_err()
+/// Validation result of one downloaded sdp.
+pub struct SdpValidity {
+    pub sdp_md5: String,
+    pub result: Result<(), Error>,
 }
 
-pub async fn validate_by_fullname(
+pub async fn check_if_sdp_needs_download(
+    rapid_store: &rapid_store::RapidStore,
+    opts: &DownloadOptions,
+    md5: &str,
+) -> bool {
+    validate_by_sdp_md5(rapid_store, opts, md5).await.is_err()
+}
+
+/// Completion of the sdp with the given rapid name, archive name or md5. Files are only checked for existence.
+pub async fn completion_by_name(
     rapid_store: &RapidStore,
     opts: &DownloadOptions,
-    fullname: &str,
-) -> Result<(), ValidityErrors> {
-    let query = metadata::query_metadata(rapid_store, opts, fullname)
-        .await
-        .map_err(ValidityErrors::MetadataQueryError)?;
-    let (_, sdp) = query.ok_or(ValidityErrors::MissingSdp)?;
-    validate_by_sdp_md5(rapid_store, &sdp.md5)
+    name: &str,
+) -> Result<Completion, Error> {
+    let md5 = metadata::resolve_sdp_md5(rapid_store, opts, name)
+        .await?
+        .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+    completion_by_sdp_md5(rapid_store, &md5)
 }
 
-pub fn validate_by_sdp_md5(rapid_store: &RapidStore, md5: &str) -> Result<(), ValidityErrors> {
-    let sdp_path = rapid_store.get_sdp_path_from_md5(md5);
+pub fn completion_by_sdp_md5(rapid_store: &RapidStore, md5: &str) -> Result<Completion, Error> {
+    let sdp_path = rapid_store.find_sdp_path_from_md5(md5);
+    if !sdp_path.exists() {
+        return Err(Error::NotFound(format!("sdp {md5}")));
+    }
+    let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path)?;
 
-    let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path).map_err(|e| {
-        ValidityErrors::MetadataQueryError(MetadataQueryError::CorruptFile(e.into()))
-    })?;
+    let missing_files = rapid_store.find_missing_files(&sdp_packages).len();
+    Ok(Completion {
+        present_files: sdp_packages.len() - missing_files,
+        missing_files,
+    })
+}
 
-    let mut files_with_errors = Vec::new();
+pub async fn validate_by_fullname(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    fullname: &str,
+) -> Result<(), Error> {
+    let query = metadata::query_metadata(rapid_store, opts, fullname).await?;
+    let (_, sdp) = query.ok_or_else(|| Error::NotFound(fullname.to_owned()))?;
+    validate_by_sdp_md5(rapid_store, opts, &sdp.md5).await
+}
 
-    for package in sdp_packages.iter() {
-        let validation = validate_sdp_package(rapid_store, package);
-        let pool_path = rapid_store.get_pool_path(package);
-        if let Some(file_error) = validation {
-            files_with_errors.push((pool_path, file_error));
-        }
+pub async fn validate_by_sdp_md5(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    md5: &str,
+) -> Result<(), Error> {
+    let sdp_path = rapid_store.find_sdp_path_from_md5(md5);
+    if !sdp_path.exists() {
+        return Err(Error::NotFound(format!("sdp {md5}")));
     }
 
+    let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path)?;
+
+    let files_with_errors = validate_sdp_packages(rapid_store, opts, &sdp_packages).await;
+
     if files_with_errors.is_empty() {
         return Ok(());
     }
 
-    Err(ValidityErrors::InvalidFiles {
+    Err(Error::Integrity {
         files: files_with_errors,
     })
 }
 
+/// Validates every downloaded sdp. Pool files shared between sdps are only validated once.
+pub async fn validate_all(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+) -> Result<Vec<SdpValidity>, Error> {
+    let mut sdps = Vec::new();
+    let mut unique_packages = Vec::new();
+    let mut seen = HashSet::new();
+    for sdp_path in rapid_store.list_all_sdp_paths()? {
+        let sdp_md5 = sdp_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let sdp_packages = parsing::load_sdp_packages_from_file(&sdp_path);
+        if let Ok(sdp_packages) = &sdp_packages {
+            for package in sdp_packages.iter() {
+                if seen.insert(package.md5) {
+                    unique_packages.push(package.clone());
+                }
+            }
+        }
+        sdps.push((sdp_md5, sdp_packages));
+    }
+
+    let file_errors: HashMap<PathBuf, FileError> =
+        validate_sdp_packages(rapid_store, opts, &unique_packages)
+            .await
+            .into_iter()
+            .collect();
+
+    Ok(sdps
+        .into_iter()
+        .map(|(sdp_md5, sdp_packages)| {
+            let result = sdp_packages.and_then(|sdp_packages| {
+                let files: Vec<(PathBuf, FileError)> = sdp_packages
+                    .iter()
+                    .map(|package| rapid_store.find_pool_path(package))
+                    .filter_map(|path| file_errors.get(&path).map(|e| (path, *e)))
+                    .collect();
+                if files.is_empty() {
+                    Ok(())
+                } else {
+                    Err(Error::Integrity { files })
+                }
+            });
+            SdpValidity { sdp_md5, result }
+        })
+        .collect())
+}
+
+/// Validates the pool files of all packages, spread over `opts.verify_concurrency` blocking workers.
+/// Errors are returned in the same order as the packages.
+///
+/// With `opts.use_verification_cache`, files unchanged since they were last verified are skipped.
+pub async fn validate_sdp_packages(
+    rapid_store: &RapidStore,
+    opts: &DownloadOptions,
+    packages: &[SdpPackage],
+) -> Vec<(PathBuf, FileError)> {
+    let mut cache = opts
+        .use_verification_cache
+        .then(|| VerificationCache::load(rapid_store));
+
+    let mut files = Vec::new();
+    let mut stamps = Vec::new();
+    for package in packages.iter() {
+        let path = rapid_store.find_pool_path(package);
+        let stamp = FileStamp::of(&path);
+        if let (Some(cache), Some(stamp)) = (&cache, &stamp) {
+            if cache.is_verified(&path, stamp) {
+                continue;
+            }
+        }
+        files.push((path, package.md5_bin));
+        stamps.push(stamp);
+    }
+    let files = Arc::new(files);
+    let next_file = Arc::new(AtomicUsize::new(0));
+
+    let workers: Vec<_> = (0..opts.verify_concurrency.clamp(1, files.len().max(1)))
+        .map(|_| {
+            let files = files.clone();
+            let next_file = next_file.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut errors = Vec::new();
+                loop {
+                    let index = next_file.fetch_add(1, Ordering::Relaxed);
+                    let Some((path, md5_bin)) = files.get(index) else {
+                        break;
+                    };
+                    if let Some(file_error) = validate_sdp_package_with_path(path, *md5_bin) {
+                        errors.push((index, file_error));
+                    }
+                }
+                errors
+            })
+        })
+        .collect();
+
+    let mut errors = Vec::new();
+    for worker in workers {
+        errors.extend(worker.await.expect("validation worker panicked"));
+    }
+    errors.sort_by_key(|(index, _)| *index);
+
+    if let Some(cache) = &mut cache {
+        for (index, ((path, _), stamp)) in files.iter().zip(stamps).enumerate() {
+            match stamp {
+                Some(stamp) if errors.binary_search_by_key(&index, |(i, _)| *i).is_err() => {
+                    cache.insert(path, stamp)
+                }
+                _ => cache.remove(path),
+            }
+        }
+        if let Err(err) = cache.save() {
+            opts.print.event(Event::Error(format!(
+                "Failed to save verification cache: {err}"
+            )));
+        }
+    }
+
+    errors
+        .into_iter()
+        .map(|(index, file_error)| (files[index].0.clone(), file_error))
+        .collect()
+}
+
 pub fn validate_sdp_package(rapid_store: &RapidStore, package: &SdpPackage) -> Option<FileError> {
-    let pool_path = rapid_store.get_pool_path(package);
+    let pool_path = rapid_store.find_pool_path(package);
     validate_sdp_package_with_path(&pool_path, package.md5_bin)
 }
 
@@ -100,13 +280,171 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn no_file() {
+    #[tokio::test]
+    async fn no_file() {
         let springdir = tempfile::tempdir().unwrap();
         let rapid_store = RapidStore::new(springdir.into_path());
+        let opts = DownloadOptions::default();
+
+        assert!(check_if_sdp_needs_download(&rapid_store, &opts, "test").await);
+        assert!(check_if_sdp_needs_download(&rapid_store, &opts, "").await);
+    }
+
+    #[tokio::test]
+    async fn validate_all_groups_by_sdp() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let first = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"shared"), ("b.lua", b"first")],
+        );
+        let second = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:2",
+            "Test 2",
+            &[("a.lua", b"shared"), ("b.lua", b"second")],
+        );
+        let shared = test_utils::pool_path(root.path(), b"shared");
+        std::fs::remove_file(&shared).unwrap();
+        std::fs::remove_file(test_utils::pool_path(root.path(), b"second")).unwrap();
+
+        let results = validate_all(&rapid_store, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        for result in results {
+            let Err(Error::Integrity { files }) = result.result else {
+                panic!("expected invalid files");
+            };
+            if result.sdp_md5 == first {
+                assert_eq!(files, vec![(shared.clone(), FileError::Missing)]);
+            } else {
+                assert_eq!(result.sdp_md5, second);
+                assert_eq!(files.len(), 2);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn verification_cache_skips_unchanged_files() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a.lua", b"first"), ("b.lua", b"second")],
+        );
+        let first = test_utils::pool_path(root.path(), b"first");
+        let second = test_utils::pool_path(root.path(), b"second");
+        let opts = DownloadOptions {
+            use_verification_cache: true,
+            ..Default::default()
+        };
+        assert!(validate_by_sdp_md5(&rapid_store, &opts, &md5).await.is_ok());
+
+        // Same size and mtime as the verified file, so only a full verification notices.
+        let mtime = std::fs::metadata(&first).unwrap().modified().unwrap();
+        let mut contents = std::fs::read(&first).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        std::fs::write(&first, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&first)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        std::fs::remove_file(&second).unwrap();
+
+        let Err(Error::Integrity { files }) = validate_by_sdp_md5(&rapid_store, &opts, &md5).await
+        else {
+            panic!("expected invalid files");
+        };
+        assert_eq!(files, vec![(second.clone(), FileError::Missing)]);
+
+        let Err(Error::Integrity { files }) =
+            validate_by_sdp_md5(&rapid_store, &DownloadOptions::default(), &md5).await
+        else {
+            panic!("expected invalid files");
+        };
+        assert_eq!(files.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn completion_of_partial_download() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let opts = DownloadOptions::new(MetadataSource::Local);
+        let md5 = test_utils::add_local_archive(
+            root.path(),
+            "test",
+            "test:1",
+            "Test 1",
+            &[("a", b"a"), ("b", b"b"), ("c", b"c"), ("d", b"d")],
+        );
+        std::fs::remove_file(test_utils::pool_path(root.path(), b"c")).unwrap();
 
-        assert!(check_if_sdp_needs_download(&rapid_store, "test"));
-        assert!(check_if_sdp_needs_download(&rapid_store, ""));
+        for name in ["test:1", "Test 1", md5.as_str()] {
+            let completion = completion_by_name(&rapid_store, &opts, name).await.unwrap();
+            assert_eq!(completion.present_files, 3);
+            assert_eq!(completion.missing_files, 1);
+            assert_eq!(completion.percentage(), 75.0);
+        }
+        assert!(matches!(
+            completion_by_name(&rapid_store, &opts, "test:2").await,
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_files_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let contents: Vec<Vec<u8>> = (0..20).map(|i| format!("file {i}").into_bytes()).collect();
+        let files: Vec<(String, &[u8])> = contents
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (format!("{i}.lua"), c.as_slice()))
+            .collect();
+        let files: Vec<(&str, &[u8])> = files.iter().map(|(n, c)| (n.as_str(), *c)).collect();
+        let md5 = test_utils::add_local_archive(root.path(), "test", "test:1", "Test 1", &files);
+
+        let missing = test_utils::pool_path(root.path(), &contents[3]);
+        let corrupt = test_utils::pool_path(root.path(), &contents[11]);
+        let wrong_hash = test_utils::pool_path(root.path(), &contents[17]);
+        std::fs::remove_file(&missing).unwrap();
+        std::fs::write(&corrupt, b"not gzipped").unwrap();
+        std::fs::copy(
+            test_utils::pool_path(root.path(), &contents[0]),
+            &wrong_hash,
+        )
+        .unwrap();
+
+        for verify_concurrency in [1, 3, 64] {
+            let opts = DownloadOptions {
+                verify_concurrency,
+                ..Default::default()
+            };
+            let Err(Error::Integrity { files }) =
+                validate_by_sdp_md5(&rapid_store, &opts, &md5).await
+            else {
+                panic!("expected invalid files");
+            };
+            assert!(matches!(
+                files.as_slice(),
+                [
+                    (p1, FileError::Missing),
+                    (p2, FileError::Corrupt),
+                    (p3, FileError::WrongHash)
+                ] if *p1 == missing && *p2 == corrupt && *p3 == wrong_hash
+            ));
+        }
     }
 
     #[tokio::test]
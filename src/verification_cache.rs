@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::{error::Error, rapid::rapid_store::RapidStore};
+
+const CACHE_FILE: &str = "sprd-verification-cache";
+
+/// Size and modification time of a file, used to detect changes since it was verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    size: u64,
+    mtime_nanos: u128,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            mtime_nanos: mtime.as_nanos(),
+        })
+    }
+}
+
+/// Pool files known to be valid, stored in the root as `<size> <mtime> <relative path>` lines.
+pub struct VerificationCache {
+    root: PathBuf,
+    entries: HashMap<PathBuf, FileStamp>,
+    /// Changed since it was loaded
+    dirty: bool,
+}
+
+impl VerificationCache {
+    /// Loads the cache, starting over if it's missing or unreadable.
+    pub fn load(rapid_store: &RapidStore) -> Self {
+        let root = rapid_store.root.clone();
+        let entries = fs::read_to_string(root.join(CACHE_FILE))
+            .map(|contents| contents.lines().filter_map(parse_line).collect())
+            .unwrap_or_default();
+
+        Self {
+            root,
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub fn is_verified(&self, path: &Path, stamp: &FileStamp) -> bool {
+        path.strip_prefix(&self.root)
+            .ok()
+            .and_then(|relative| self.entries.get(relative))
+            == Some(stamp)
+    }
+
+    pub fn insert(&mut self, path: &Path, stamp: FileStamp) {
+        if let Ok(relative) = path.strip_prefix(&self.root) {
+            if self.entries.insert(relative.to_owned(), stamp) != Some(stamp) {
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        if let Ok(relative) = path.strip_prefix(&self.root) {
+            if self.entries.remove(relative).is_some() {
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Writes the cache if it changed. Readers holding a shared lock may save concurrently,
+    /// so each writes its own temp file and the last rename wins.
+    pub fn save(&self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut contents = String::new();
+        for (path, stamp) in self.entries.iter() {
+            if let Some(path) = path.to_str() {
+                contents.push_str(&format!("{} {} {path}\n", stamp.size, stamp.mtime_nanos));
+            }
+        }
+
+        let path = self.root.join(CACHE_FILE);
+        let mut temp_file =
+            tempfile::NamedTempFile::new_in(&self.root).map_err(|e| Error::filesystem(&path, e))?;
+        temp_file
+            .write_all(contents.as_bytes())
+            .map_err(|e| Error::filesystem(temp_file.path(), e))?;
+        temp_file
+            .persist(&path)
+            .map_err(|e| Error::filesystem(&path, e.error))?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, FileStamp)> {
+    let mut parts = line.splitn(3, ' ');
+    let size = parts.next()?.parse().ok()?;
+    let mtime_nanos = parts.next()?.parse().ok()?;
+    let path = PathBuf::from(parts.next()?);
+
+    Some((path, FileStamp { size, mtime_nanos }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_only_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let rapid_store = RapidStore::new(root.path().to_owned());
+        let pool_file = root.path().join("pool/00/file.gz");
+        fs::create_dir_all(pool_file.parent().unwrap()).unwrap();
+        fs::write(&pool_file, b"contents").unwrap();
+        let stamp = FileStamp::of(&pool_file).unwrap();
+
+        let mut cache = VerificationCache::load(&rapid_store);
+        cache.remove(&pool_file);
+        cache.save().unwrap();
+        assert!(!root.path().join(CACHE_FILE).exists());
+
+        cache.insert(&pool_file, stamp);
+        cache.save().unwrap();
+        let mut cache = VerificationCache::load(&rapid_store);
+        assert!(cache.is_verified(&pool_file, &stamp));
+        cache.insert(&pool_file, stamp);
+        assert!(!cache.dirty);
+
+        // Only the cache file is left, no temp files
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 2);
+    }
+}
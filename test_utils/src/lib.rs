@@ -1,8 +1,13 @@
 use std::{
+    fs,
+    io::{Read, Write},
     path::{Path, PathBuf},
     process::Command,
 };
 
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use md5::{Digest, Md5};
+
 use sprd::{api, rapid::rapid_store, rapid_download};
 
 // These tests require that you have pr-downloader installed and available in Path.
@@ -47,3 +52,125 @@ pub async fn setup_sprd_folders() -> PathBuf {
 
     root_folder
 }
+
+// Offline fixtures: build a rapid root by hand, without pr-downloader or network access.
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(path: &Path) -> String {
+    let mut s = String::new();
+    GzDecoder::new(fs::File::open(path).unwrap())
+        .read_to_string(&mut s)
+        .unwrap();
+    s
+}
+
+fn append_gz_line(path: &Path, line: &str) {
+    let mut contents = if path.exists() {
+        gunzip(path)
+    } else {
+        String::new()
+    };
+    if contents.lines().any(|l| l == line) {
+        return;
+    }
+    contents.push_str(line);
+    contents.push('\n');
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, gzip(contents.as_bytes())).unwrap();
+}
+
+/// Path of the pool file holding `contents`.
+pub fn pool_path(root: &Path, contents: &[u8]) -> PathBuf {
+    let md5 = format!("{:x}", Md5::digest(contents));
+    root.join(format!("pool/{}/{}.gz", &md5[..2], &md5[2..]))
+}
+
+/// Writes a complete archive into `root`: registry and versions.gz entries, the sdp and its pool files.
+/// Returns the md5 of the sdp.
+pub fn add_local_archive(
+    root: &Path,
+    repo: &str,
+    rapid_name: &str,
+    archive_name: &str,
+    files: &[(&str, &[u8])],
+) -> String {
+    let mut sdp = Vec::new();
+    let mut hasher = Md5::new();
+    hasher.update(rapid_name);
+    for (name, contents) in files {
+        let md5 = Md5::digest(contents);
+        hasher.update(md5);
+
+        sdp.push(name.len() as u8);
+        sdp.extend_from_slice(name.as_bytes());
+        sdp.extend_from_slice(&md5);
+        sdp.extend_from_slice(&[0; 4]);
+        sdp.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+
+        let dest = pool_path(root, contents);
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(dest, gzip(contents)).unwrap();
+    }
+    let sdp_md5 = format!("{:x}", hasher.finalize());
+
+    fs::create_dir_all(root.join("packages")).unwrap();
+    fs::write(root.join(format!("packages/{sdp_md5}.sdp")), gzip(&sdp)).unwrap();
+
+    append_gz_line(
+        &root.join("rapid/repos.springrts.com/repos.gz"),
+        &format!("{repo},https://repos.springrts.com/{repo},,"),
+    );
+    append_gz_line(
+        &root.join(format!("rapid/repos.springrts.com/{repo}/versions.gz")),
+        &format!("{rapid_name},{sdp_md5},,{archive_name}"),
+    );
+
+    sdp_md5
+}
+
+/// Serves `files` over HTTP on a free local port until the runtime stops. Requests are matched
+/// on their path and query; anything else is a 404. Returns the base URL.
+pub fn serve_files(files: Vec<(String, Vec<u8>)>) -> String {
+    use std::{convert::Infallible, sync::Arc};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server, StatusCode,
+    };
+
+    let files = Arc::new(files);
+    let make_service = make_service_fn(move |_| {
+        let files = files.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
+                let files = files.clone();
+                async move {
+                    let requested = req
+                        .uri()
+                        .path_and_query()
+                        .map(|path| path.as_str())
+                        .unwrap_or_default();
+                    let response = match files.iter().find(|(path, _)| path == requested) {
+                        Some((_, contents)) => Response::new(Body::from(contents.clone())),
+                        None => {
+                            let mut response = Response::new(Body::empty());
+                            *response.status_mut() = StatusCode::NOT_FOUND;
+                            response
+                        }
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
+}